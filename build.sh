@@ -4,7 +4,7 @@
 set -e
 
 REQUIRED_CMDS=(rustc cargo)
-REQUIRED_CRATES=(which dirs rustyline serde serde_json glob)
+REQUIRED_CRATES=(which dirs rustyline serde serde_json glob clap chrono flate2 sha1_smol toml libc)
 
 missing_cmds=()
 missing_crates=()
@@ -0,0 +1,105 @@
+use crate::config::FailurePolicy;
+use crate::scan::ExportFormat;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+/// A cross-platform Rust utility to locate and run Bash scripts.
+///
+/// Without a subcommand magish starts the interactive prompt.
+#[derive(Parser, Debug)]
+#[command(name = "magish", version)]
+pub struct Cli {
+    /// Don't print the banner when starting the interactive prompt
+    #[arg(long, global = true)]
+    pub no_banner: bool,
+
+    /// Only print the output of scripts, not magish's own messages
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Directory to work in instead of the last one used
+    #[arg(long, global = true, value_name = "DIR")]
+    pub cwd: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run a script and exit with its status
+    Run {
+        /// What to do when a command fails: stop, continue or prompt
+        #[arg(long, value_name = "POLICY")]
+        on_error: Option<FailurePolicy>,
+
+        /// Show what would run without running anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Pause before each command to run, skip or edit it
+        #[arg(long)]
+        step: bool,
+
+        /// Run a task's dependencies that don't depend on each other at the
+        /// same time
+        #[arg(long)]
+        parallel: bool,
+
+        /// Script path, its number in `magish list`, or a task from magish.toml
+        script: String,
+
+        /// Arguments passed to the script as $1..$n
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Search the filesystem for scripts
+    Scan(ScanArgs),
+    /// List the scripts in the working directory
+    List,
+    /// List the tasks in magish.toml
+    Tasks,
+    /// Print the command history
+    History,
+    /// Print the current configuration
+    Config,
+}
+
+/// Options of `scan`, shared by the subcommand and the prompt command.
+#[derive(Args, Debug)]
+pub struct ScanArgs {
+    /// Also save the list to FILE
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Format of the saved list: text, json, csv or ndjson [default: from
+    /// the FILE extension, else text]
+    #[arg(long, value_name = "FORMAT")]
+    pub format: Option<ExportFormat>,
+
+    /// How many folder levels below each root to search
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    /// Folder to search instead of the defaults; can be repeated
+    #[arg(long = "root", value_name = "DIR")]
+    pub roots: Vec<PathBuf>,
+
+    /// Ignore the saved index and scan everything again
+    #[arg(long)]
+    pub rebuild: bool,
+}
+
+/// Parses the words after `scan` at the prompt.
+#[derive(Parser, Debug)]
+#[command(name = "scan", no_binary_name = true, disable_help_flag = true)]
+struct ScanLine {
+    #[command(flatten)]
+    args: ScanArgs,
+}
+
+impl ScanArgs {
+    pub fn parse_words(words: &[String]) -> Result<Self, clap::Error> {
+        ScanLine::try_parse_from(words).map(|line| line.args)
+    }
+}
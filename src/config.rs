@@ -1,45 +1,741 @@
+use crate::detect::Interpreter;
+use crate::theme::Theme;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Name of the per-project config file, looked for in the working folder
+/// and its parents.
+const PROJECT_CONFIG: &str = ".magish.json";
+
+/// File next to the executable that turns on portable mode.
+const PORTABLE_MARKER: &str = "magish.portable";
+
+/// Layout version of config files, saved in them as `"version"`. Files
+/// without one (version 0) were written before config files were layered,
+/// when every setting was saved whether it had been changed or not.
+const CONFIG_VERSION: u64 = 1;
+
+/// Settings only read from the user's config, since the system and project
+/// configs can't be changed from magish: a bookmark removed at the prompt
+/// would come back from them.
+const USER_ONLY_SETTINGS: [&str; 1] = ["bookmarks"];
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
 pub struct Config {
     pub last_directory: PathBuf,
+    /// Named folders for `go <name>` and `cd @name`; see `USER_ONLY_SETTINGS`.
+    pub bookmarks: BTreeMap<String, PathBuf>,
     pub history_limit: usize,
+    /// Don't add a command to the history if it repeats the previous one.
+    pub history_ignore_dups: bool,
+    /// Don't add commands typed with a leading space to the history.
+    pub history_ignore_space: bool,
+    /// Commands that are never added to the history.
+    pub history_exclude: Vec<String>,
+    pub execution_mode: ExecutionMode,
+    pub on_error: FailurePolicy,
+    /// Copy script output into the run log. When off, scripts write straight
+    /// to the terminal, keeping its colors and progress bars.
+    pub log_output: bool,
+    /// Interpreter for scripts that name none in their shebang, an
+    /// `@executor` tag or their extension.
+    pub executor: Interpreter,
+    /// Order of the script list.
+    pub script_sort: ScriptSort,
+    /// How many levels of subfolders the script list includes.
+    pub list_depth: usize,
+    /// Globs in `.gitignore` syntax that `scan` skips, on top of the
+    /// `.gitignore` files it finds.
+    pub scan_exclude: Vec<String>,
+    /// Prompt template; see `prompt::render` for the placeholders.
+    pub prompt: String,
+    pub theme: Theme,
+    #[serde(skip)]
+    layers: Layers,
+}
+
+/// The config files a `Config` was merged from.
+#[derive(Debug, Default)]
+struct Layers {
+    /// The defaults with the system-wide config on top.
+    base: Value,
+    /// The user's config file, the only one `save` writes.
+    user: Layer,
+    /// The nearest `.magish.json`.
+    project: Option<(PathBuf, Layer)>,
+    /// What all layers merged to, to tell which settings changed since.
+    merged: Value,
+}
+
+/// One config file.
+#[derive(Debug, Default, Clone)]
+struct Layer {
+    /// Everything in the file, including unknown and invalid settings, so
+    /// that saving doesn't lose them.
+    raw: Map<String, Value>,
+    /// The settings that can be used.
+    valid: Map<String, Value>,
+    /// Whether the file was written for an older `CONFIG_VERSION`.
+    outdated: bool,
+}
+
+/// How `execute_script` hands a script's commands to bash.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionMode {
+    /// One bash process for the whole script; shell state carries between commands.
+    #[default]
+    Session,
+    /// A fresh `bash -c` for every command.
+    PerLine,
+}
+
+/// What to do when a script command exits with a non-zero status.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+    /// Stop the script at the first failing command.
+    Stop,
+    /// Keep going, like plain bash without `set -e`.
+    #[default]
+    Continue,
+    /// Ask whether to retry the command, skip it or abort the script.
+    Prompt,
+}
+
+impl FromStr for FailurePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stop" => Ok(Self::Stop),
+            "continue" => Ok(Self::Continue),
+            "prompt" => Ok(Self::Prompt),
+            _ => Err(format!("Unknown failure policy '{}' (expected stop, continue or prompt)", s)),
+        }
+    }
+}
+
+/// Order of the numbered script list.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptSort {
+    #[default]
+    Name,
+    /// Most recently modified first.
+    Modified,
+    /// Most often run first, counted from the run history.
+    Usage,
+}
+
+impl FromStr for ScriptSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(Self::Name),
+            "modified" => Ok(Self::Modified),
+            "usage" => Ok(Self::Usage),
+            _ => Err(format!("Unknown sort order '{}' (expected name, modified or usage)", s)),
+        }
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             last_directory: dirs::home_dir().unwrap_or_else(|| PathBuf::from(".")),
+            bookmarks: BTreeMap::new(),
             history_limit: 100,
+            history_ignore_dups: true,
+            history_ignore_space: true,
+            history_exclude: vec!["quit".to_string(), "exit".to_string()],
+            execution_mode: ExecutionMode::default(),
+            on_error: FailurePolicy::default(),
+            log_output: true,
+            executor: Interpreter::Bash,
+            script_sort: ScriptSort::default(),
+            list_depth: 0,
+            scan_exclude: [".*/", "node_modules/", "target/", "vendor/"].map(String::from).to_vec(),
+            prompt: "{cwd}> ".to_string(),
+            theme: Theme::default(),
+            layers: Layers::default(),
         }
     }
 }
 
 impl Config {
+    /// Loads the defaults, then `/etc/magish/configs.json`, then the user's
+    /// config on top. `enter` adds the project layer. Problems with the files
+    /// are reported on stderr and the settings concerned left at the layer
+    /// below.
     pub fn load() -> Self {
+        migrate_portable_files();
+        let mut base = serde_json::to_value(Config::default()).unwrap_or_default();
+        if let Some(path) = system_config_path() {
+            if let Some(system) = read_layer(&path, false).map(|layer| without_user_settings(&path, layer)) {
+                merge(&mut base, Value::Object(system.valid));
+            }
+        }
         let config_path = Self::get_config_path();
-        if let Ok(contents) = fs::read_to_string(&config_path) {
-            serde_json::from_str(&contents).unwrap_or_default()
-        } else {
-            let config = Config::default();
-            let _ = config.save();
-            config
+        let user = read_layer(&config_path, true).unwrap_or_default();
+        let outdated = user.outdated;
+        let config = Self::from_layers(Layers { base, user, project: None, merged: Value::Null });
+        if outdated {
+            match backup(&config_path).and_then(|backup| config.save().map(|_| backup)) {
+                Ok(backup) => eprintln!(
+                    "Updated {} to config version {}; the old file was saved as {}",
+                    config_path.display(),
+                    CONFIG_VERSION,
+                    backup.display()
+                ),
+                Err(e) => eprintln!("Failed to update {}: {}", config_path.display(), e),
+            }
         }
+        config
     }
 
+    /// Applies the `.magish.json` nearest to `dir`, replacing the one of the
+    /// previous folder. Settings changed in this session are kept.
+    pub fn enter(&mut self, dir: &Path) {
+        let path = dir.ancestors().map(|folder| folder.join(PROJECT_CONFIG)).find(|path| path.is_file());
+        if path.as_ref() == self.layers.project.as_ref().map(|(path, _)| path) {
+            return;
+        }
+        let project = path.and_then(|path| Some((path.clone(), without_user_settings(&path, read_layer(&path, false)?))));
+        if let Some((path, project)) = &project {
+            if !project.valid.is_empty() {
+                let keys: Vec<&str> = project.valid.keys().map(String::as_str).collect();
+                eprintln!("Using settings from {}: {}", path.display(), keys.join(", "));
+            }
+        }
+        let changes = self.changes();
+        let mut layers = std::mem::take(&mut self.layers);
+        apply_changes(&mut layers.user.raw, changes.clone());
+        apply_changes(&mut layers.user.valid, changes);
+        layers.project = project;
+        *self = Self::from_layers(layers);
+    }
+
+    /// Writes the user's config file: what it held before plus the settings
+    /// changed in this session. Values from the system and project layers
+    /// stay out of it. A file that can't be read is backed up first.
     pub fn save(&self) -> std::io::Result<()> {
         let config_path = Self::get_config_path();
         if let Some(parent) = config_path.parent() {
             fs::create_dir_all(parent)?;
         }
-        let contents = serde_json::to_string_pretty(self)?;
+        if let Ok(existing) = fs::read_to_string(&config_path) {
+            if !matches!(serde_json::from_str(&existing), Ok(Value::Object(_))) {
+                let backup = backup(&config_path)?;
+                eprintln!("Saved the unreadable {} as {}", config_path.display(), backup.display());
+            }
+        }
+        let mut user = self.layers.user.raw.clone();
+        apply_changes(&mut user, self.changes());
+        // A file from a newer magish keeps its version.
+        user.entry("version").or_insert(CONFIG_VERSION.into());
+        let contents = serde_json::to_string_pretty(&user)?;
         fs::write(&config_path, contents)
     }
 
-    fn get_config_path() -> PathBuf {
-        let exe_path = std::env::current_exe().unwrap_or_else(|_| PathBuf::from("."));
-        exe_path.parent().unwrap_or(Path::new(".")).join("configs.json")
+    /// Every setting with its value; nested ones are named like `theme.hint`.
+    pub fn list(&self) -> Vec<(String, Value)> {
+        let mut settings = Vec::new();
+        flatten("", serde_json::to_value(self).unwrap_or_default(), &mut settings);
+        settings
+    }
+
+    /// The value of setting `key`, e.g. `history_limit` or `theme.hint`.
+    pub fn get(&self, key: &str) -> Result<Value, String> {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|settings| settings.pointer(&pointer(key)).cloned())
+            .ok_or_else(|| format!("Unknown setting '{}'", key))
+    }
+
+    /// Sets `key` to `text`, read as JSON if it parses as a value of the
+    /// right type and as a plain string otherwise.
+    pub fn set(&mut self, key: &str, text: &str) -> Result<(), String> {
+        self.get(key)?;
+        let mut error = String::new();
+        let parsed: Option<Value> = serde_json::from_str(text).ok();
+        for value in parsed.into_iter().chain([Value::String(text.to_string())]) {
+            let mut settings = serde_json::to_value(&*self).map_err(|e| e.to_string())?;
+            if let Some(setting) = settings.pointer_mut(&pointer(key)) {
+                *setting = value;
+            }
+            match serde_json::from_value::<Config>(settings) {
+                Ok(mut updated) => {
+                    updated.layers = std::mem::take(&mut self.layers);
+                    *self = updated;
+                    return Ok(());
+                }
+                Err(e) if error.is_empty() => error = e.to_string(),
+                Err(_) => {}
+            }
+        }
+        Err(format!("Invalid value for {}: {}", key, error))
+    }
+
+    /// Removes `key` from the user's config, so the system or project config
+    /// or the default applies again.
+    pub fn reset(&mut self, key: &str) -> Result<(), String> {
+        self.get(key)?;
+        let changes = self.changes();
+        let mut layers = std::mem::take(&mut self.layers);
+        for settings in [&mut layers.user.raw, &mut layers.user.valid] {
+            apply_changes(settings, changes.clone());
+            remove_setting(settings, key);
+        }
+        *self = Self::from_layers(layers);
+        Ok(())
+    }
+
+    /// Loads the config files again, e.g. after they were edited, with the
+    /// project config for `dir`. Fails, leaving the config as it was, if the
+    /// user's file isn't a JSON object.
+    pub fn reload(&mut self, dir: &Path) -> Result<(), String> {
+        if let Ok(contents) = fs::read_to_string(Self::get_config_path()) {
+            match serde_json::from_str(&contents) {
+                Ok(Value::Object(_)) => {}
+                Ok(_) => return Err("expected a JSON object".to_string()),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        *self = Self::load();
+        self.enter(dir);
+        Ok(())
+    }
+
+    /// Folder for history, run logs and the script index: `$XDG_STATE_HOME/magish`
+    /// (the local data folder on Windows and macOS), or the executable's
+    /// folder in portable mode.
+    pub fn state_dir() -> PathBuf {
+        if is_portable() {
+            return exe_dir();
+        }
+        dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .map(|dir| dir.join("magish"))
+            .unwrap_or_else(exe_dir)
+    }
+
+    fn from_layers(mut layers: Layers) -> Self {
+        let mut merged = layers.base.clone();
+        merge(&mut merged, Value::Object(layers.user.valid.clone()));
+        if let Some((_, project)) = &layers.project {
+            merge(&mut merged, Value::Object(project.valid.clone()));
+        }
+        let mut config: Config = serde_json::from_value(merged.clone()).unwrap_or_default();
+        layers.merged = merged;
+        config.layers = layers;
+        config
+    }
+
+    /// The settings that differ from what was loaded, down to single keys of
+    /// objects such as `theme`, in the form `apply_changes` takes.
+    fn changes(&self) -> Map<String, Value> {
+        let current = serde_json::to_value(self).unwrap_or_default();
+        match diff(&self.layers.merged, current) {
+            Some(Value::Object(changes)) => changes,
+            _ => Map::new(),
+        }
+    }
+
+    /// `$XDG_CONFIG_HOME/magish/configs.json` (the usual config folder on
+    /// Windows and macOS), or next to the executable in portable mode.
+    pub fn get_config_path() -> PathBuf {
+        let dir = match dirs::config_dir() {
+            Some(dir) if !is_portable() => dir.join("magish"),
+            _ => exe_dir(),
+        };
+        dir.join("configs.json")
+    }
+}
+
+/// Portable mode keeps every file next to the executable, as magish did
+/// before it used the XDG folders. It is on if `MAGISH_PORTABLE` is set to
+/// anything but `0`, or if a `magish.portable` file sits next to the
+/// executable.
+fn is_portable() -> bool {
+    match std::env::var("MAGISH_PORTABLE") {
+        Ok(value) if !value.is_empty() => value != "0",
+        _ => exe_dir().join(PORTABLE_MARKER).exists(),
+    }
+}
+
+fn exe_dir() -> PathBuf {
+    let exe_path = std::env::current_exe().unwrap_or_else(|_| PathBuf::from("."));
+    exe_path.parent().unwrap_or(Path::new(".")).to_path_buf()
+}
+
+fn system_config_path() -> Option<PathBuf> {
+    cfg!(unix).then(|| PathBuf::from("/etc/magish/configs.json"))
+}
+
+/// Reads one config file. The `user`'s file is brought up to
+/// `CONFIG_VERSION`; the system and project files are hand-written, so they
+/// are read as the current version even without a `"version"`. A file that
+/// isn't a JSON object is skipped, and settings that are unknown or have the
+/// wrong type are left out of `valid`, with a warning for each.
+fn read_layer(path: &Path, user: bool) -> Option<Layer> {
+    let contents = fs::read_to_string(path).ok()?;
+    let mut raw = match serde_json::from_str(&contents) {
+        Ok(Value::Object(raw)) => raw,
+        Ok(_) => {
+            eprintln!("Ignoring {}: expected a JSON object", path.display());
+            return None;
+        }
+        Err(e) => {
+            eprintln!("Ignoring {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let outdated = user && migrate(path, &mut raw);
+
+    let defaults = serde_json::to_value(Config::default()).unwrap_or_default();
+    let positions = key_positions(&contents);
+    let mut valid = Map::new();
+    for (key, value) in &raw {
+        if key == "version" {
+            continue;
+        }
+        let mut problems = Vec::new();
+        match defaults.get(key) {
+            None => problems.push("unknown setting, ignored".to_string()),
+            Some(default) => {
+                let mut setting = Map::new();
+                setting.insert(key.clone(), value.clone());
+                match serde_json::from_value::<Config>(Value::Object(setting)) {
+                    Ok(_) => {
+                        valid.insert(key.clone(), value.clone());
+                        // Maps such as `bookmarks` have no fixed keys to check.
+                        if let (Value::Object(default), Value::Object(value)) = (default, value) {
+                            for name in value.keys().filter(|name| !default.is_empty() && !default.contains_key(*name)) {
+                                problems.push(format!("unknown setting `{}.{}`, ignored", key, name));
+                            }
+                        }
+                    }
+                    Err(e) => problems.push(format!("{}; using the default", e)),
+                }
+            }
+        }
+        let (line, column) = positions.get(key).copied().unwrap_or((1, 1));
+        for problem in problems {
+            eprintln!("{}: `{}` at line {} column {}: {}", path.display(), key, line, column, problem);
+        }
+    }
+    Some(Layer { raw, valid, outdated })
+}
+
+/// Leaves the `USER_ONLY_SETTINGS` out of the system or project config at
+/// `path`, with a warning if it had any.
+fn without_user_settings(path: &Path, mut layer: Layer) -> Layer {
+    for key in USER_ONLY_SETTINGS {
+        if layer.valid.remove(key).is_some() {
+            eprintln!("{}: `{}` is only read from the user's config, ignored", path.display(), key);
+        }
+    }
+    layer
+}
+
+/// Brings a config file written by an older magish up to `CONFIG_VERSION`.
+/// Returns whether anything had to change.
+fn migrate(path: &Path, layer: &mut Map<String, Value>) -> bool {
+    let version = layer.get("version").and_then(Value::as_u64).unwrap_or(0);
+    if version > CONFIG_VERSION {
+        eprintln!(
+            "{} is for config version {}, newer than this magish understands ({}); some settings may be ignored",
+            path.display(),
+            version,
+            CONFIG_VERSION
+        );
+        return false;
+    }
+    if version < 1 {
+        // Keep only what differs from the defaults, so that the system and
+        // project layers and future defaults aren't hidden by copies of the
+        // old ones.
+        let defaults = serde_json::to_value(Config::default()).unwrap_or_default();
+        layer.retain(|key, value| defaults.get(key) != Some(value));
+    }
+    layer.insert("version".to_string(), CONFIG_VERSION.into());
+    version < CONFIG_VERSION
+}
+
+/// Copies `path` to `<path>.<time>.bak`, e.g. `configs.json.20240131-154500.bak`,
+/// next to any older backups.
+fn backup(path: &Path) -> std::io::Result<PathBuf> {
+    let time = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let name = |suffix: String| {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(format!(".{}{}.bak", time, suffix));
+        PathBuf::from(backup)
+    };
+    let backup = (1..)
+        .map(|n| name(if n == 1 { String::new() } else { format!("-{}", n) }))
+        .find(|backup| !backup.exists())
+        .unwrap_or_else(|| name(String::new()));
+    fs::copy(path, &backup)?;
+    Ok(backup)
+}
+
+/// Line and column (both from 1) of each key of the top-level object in
+/// `json`, to point at settings that `serde_json::Value` has no position for.
+fn key_positions(json: &str) -> HashMap<String, (usize, usize)> {
+    let mut positions = HashMap::new();
+    let (mut line, mut column, mut depth) = (1, 0, 0);
+    let mut expect_key = false;
+    let mut chars = json.chars();
+    while let Some(c) = chars.next() {
+        column += 1;
+        match c {
+            '\n' => {
+                line += 1;
+                column = 0;
+            }
+            '{' | '[' => {
+                depth += 1;
+                expect_key = c == '{' && depth == 1;
+            }
+            '}' | ']' => depth -= 1,
+            ',' => expect_key = depth == 1,
+            '"' => {
+                let start = (line, column);
+                let mut text = String::new();
+                while let Some(c) = chars.next() {
+                    column += 1;
+                    match c {
+                        '"' => break,
+                        '\\' => {
+                            text.extend(chars.next());
+                            column += 1;
+                        }
+                        _ => text.push(c),
+                    }
+                }
+                if expect_key {
+                    positions.entry(text).or_insert(start);
+                    expect_key = false;
+                }
+            }
+            _ => {}
+        }
+    }
+    positions
+}
+
+/// Turns a dotted setting name into a JSON pointer.
+fn pointer(key: &str) -> String {
+    format!("/{}", key.replace('.', "/"))
+}
+
+fn flatten(prefix: &str, value: Value, settings: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let name = if prefix.is_empty() { key } else { format!("{}.{}", prefix, key) };
+                flatten(&name, value, settings);
+            }
+        }
+        value => settings.push((prefix.to_string(), value)),
+    }
+}
+
+fn remove_setting(settings: &mut Map<String, Value>, key: &str) {
+    match key.split_once('.') {
+        Some((outer, rest)) => {
+            if let Some(Value::Object(inner)) = settings.get_mut(outer) {
+                remove_setting(inner, rest);
+            }
+        }
+        None => {
+            settings.remove(key);
+        }
+    }
+}
+
+/// Lays `layer` over `base`. Objects are merged key by key, so a layer can
+/// change one theme color without repeating the rest; anything else replaces
+/// the value below.
+fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// What changed from `old` to `new`: the values of objects that differ,
+/// recursively, with `null` for keys that were removed. `None` if nothing did.
+fn diff(old: &Value, new: Value) -> Option<Value> {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let mut changes: Map<String, Value> =
+                old.keys().filter(|key| !new.contains_key(*key)).map(|key| (key.clone(), Value::Null)).collect();
+            for (key, value) in new {
+                if let Some(change) = diff(old.get(&key).unwrap_or(&Value::Null), value) {
+                    changes.insert(key, change);
+                }
+            }
+            (!changes.is_empty()).then_some(Value::Object(changes))
+        }
+        (old, new) => (*old != new).then_some(new),
+    }
+}
+
+/// Writes `changes` from `diff` into `settings`, key by key, removing the
+/// keys set to `null`.
+fn apply_changes(settings: &mut Map<String, Value>, changes: Map<String, Value>) {
+    for (key, change) in changes {
+        match (settings.get_mut(&key), change) {
+            (_, Value::Null) => {
+                settings.remove(&key);
+            }
+            (Some(Value::Object(inner)), Value::Object(change)) => apply_changes(inner, change),
+            (_, change) => {
+                settings.insert(key, change);
+            }
+        }
+    }
+}
+
+/// Copies the config and history that older versions kept next to the
+/// executable into the XDG folders, the first time magish runs without
+/// portable mode.
+fn migrate_portable_files() {
+    if is_portable() {
+        return;
+    }
+    let moves = [
+        (exe_dir().join("configs.json"), Config::get_config_path()),
+        (exe_dir().join("magish-history.txt"), Config::state_dir().join("magish-history.txt")),
+    ];
+    for (old, new) in moves {
+        if old == new || !old.is_file() || new.exists() {
+            continue;
+        }
+        if let Some(parent) = new.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Err(e) = fs::copy(&old, &new) {
+            eprintln!("Failed to copy {} to {}: {}", old.display(), new.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Writes `contents` to a user config file in a fresh temp folder and
+    /// reads it.
+    fn read(name: &str, contents: &str) -> Option<Layer> {
+        read_as(name, contents, true)
+    }
+
+    fn read_as(name: &str, contents: &str, user: bool) -> Option<Layer> {
+        let dir = std::env::temp_dir().join(format!("magish-config-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("configs.json");
+        fs::write(&path, contents).unwrap();
+        let layer = read_layer(&path, user);
+        let _ = fs::remove_dir_all(&dir);
+        layer
+    }
+
+    fn object(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => panic!("not an object"),
+        }
+    }
+
+    #[test]
+    fn key_positions_finds_top_level_keys_only() {
+        let positions = key_positions("{\n  \"a\": 1,\n  \"theme\": {\"hint\": \"2\"},\n  \"b\": [\"x\", \"y\"], \"c\": \"q\\\"\"\n}");
+        assert_eq!(positions.get("a"), Some(&(2, 3)));
+        assert_eq!(positions.get("theme"), Some(&(3, 3)));
+        assert_eq!(positions.get("b"), Some(&(4, 3)));
+        assert_eq!(positions.get("c"), Some(&(4, 20)));
+        assert!(!positions.contains_key("hint"));
+        assert!(!positions.contains_key("x"));
+    }
+
+    #[test]
+    fn migrate_drops_defaults_from_version_0() {
+        let mut layer = object(json!({"history_limit": 100, "list_depth": 3, "execution_mode": "session"}));
+        assert!(migrate(Path::new("configs.json"), &mut layer));
+        assert_eq!(Value::Object(layer), json!({"list_depth": 3, "version": CONFIG_VERSION}));
+    }
+
+    #[test]
+    fn migrate_leaves_current_and_newer_files() {
+        let mut current = object(json!({"history_limit": 100, "version": CONFIG_VERSION}));
+        assert!(!migrate(Path::new("configs.json"), &mut current));
+        assert_eq!(current.get("history_limit"), Some(&json!(100)));
+
+        let mut newer = object(json!({"history_limit": 100, "version": CONFIG_VERSION + 1}));
+        assert!(!migrate(Path::new("configs.json"), &mut newer));
+        assert_eq!(newer.get("version"), Some(&json!(CONFIG_VERSION + 1)));
+    }
+
+    #[test]
+    fn merge_replaces_values_and_merges_objects() {
+        let mut base = json!({"list_depth": 0, "theme": {"hint": "2", "warning": "1;33"}, "history_exclude": ["ls"]});
+        merge(&mut base, json!({"list_depth": 2, "theme": {"hint": ""}, "history_exclude": ["cd"]}));
+        assert_eq!(base, json!({"list_depth": 2, "theme": {"hint": "", "warning": "1;33"}, "history_exclude": ["cd"]}));
+    }
+
+    #[test]
+    fn read_layer_keeps_invalid_settings_out_of_valid() {
+        let layer = read(
+            "invalid",
+            r#"{"version": 1, "list_depth": 2, "history_limit": "many", "colour": "red", "theme": {"hint": "2", "nope": "1"}}"#,
+        )
+        .unwrap();
+        assert!(!layer.outdated);
+        assert_eq!(layer.raw.len(), 5);
+        assert_eq!(Value::Object(layer.valid), json!({"list_depth": 2, "theme": {"hint": "2", "nope": "1"}}));
+    }
+
+    #[test]
+    fn read_layer_migrates_old_files_and_skips_non_objects() {
+        let layer = read("old", r#"{"history_limit": 100, "list_depth": 1}"#).unwrap();
+        assert!(layer.outdated);
+        assert_eq!(Value::Object(layer.valid), json!({"list_depth": 1}));
+
+        assert!(read("array", "[1, 2]").is_none());
+        assert!(read("broken", "{\"list_depth\": ").is_none());
+    }
+
+    #[test]
+    fn read_layer_keeps_defaults_in_project_files() {
+        let layer = read_as("project", r#"{"history_limit": 100, "list_depth": 1}"#, false).unwrap();
+        assert!(!layer.outdated);
+        assert_eq!(Value::Object(layer.valid), json!({"history_limit": 100, "list_depth": 1}));
+    }
+
+    #[test]
+    fn diff_and_apply_changes_work_key_by_key() {
+        let old = json!({"list_depth": 0, "theme": {"hint": "2", "warning": "1;33"}, "bookmarks": {"a": "/a", "b": "/b"}});
+        let new = json!({"list_depth": 0, "theme": {"hint": "3", "warning": "1;33"}, "bookmarks": {"b": "/b", "c": "/c"}});
+        let changes = object(diff(&old, new).unwrap());
+        assert_eq!(Value::Object(changes.clone()), json!({"theme": {"hint": "3"}, "bookmarks": {"a": null, "c": "/c"}}));
+        assert!(diff(&old, old.clone()).is_none());
+
+        let mut user = object(json!({"theme": {"prompt": "1"}, "bookmarks": {"a": "/a"}}));
+        apply_changes(&mut user, changes);
+        assert_eq!(Value::Object(user), json!({"theme": {"prompt": "1", "hint": "3"}, "bookmarks": {"c": "/c"}}));
     }
 }
@@ -0,0 +1,145 @@
+//! Recognizes scripts by their shebang and exec bit as well as by extension.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The program a script is meant to be run with.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpreter {
+    Bash,
+    Sh,
+    Zsh,
+    Fish,
+    Python,
+    Node,
+}
+
+impl Interpreter {
+    /// Shells, as opposed to language interpreters. On Windows they run
+    /// through WSL.
+    pub fn is_shell(self) -> bool {
+        matches!(self, Self::Bash | Self::Sh | Self::Zsh | Self::Fish)
+    }
+
+    /// The command that runs a whole script of this kind.
+    pub fn program(self) -> &'static str {
+        match self {
+            Self::Bash => "bash",
+            Self::Sh => "sh",
+            Self::Zsh => "zsh",
+            Self::Fish => "fish",
+            Self::Python if cfg!(target_os = "windows") => "python",
+            Self::Python => "python3",
+            Self::Node => "node",
+        }
+    }
+
+    /// Recognizes the interpreter named on a shebang line (without `#!`),
+    /// including the `/usr/bin/env name` form.
+    pub fn from_shebang(shebang: &str) -> Option<Self> {
+        let mut words = shebang.split_whitespace();
+        let mut program = words.next()?.rsplit('/').next()?;
+        if program == "env" {
+            // Skip options such as `env -S`.
+            program = words.find(|word| !word.starts_with('-'))?;
+        }
+        match program {
+            "bash" => Some(Self::Bash),
+            "sh" | "dash" | "ash" => Some(Self::Sh),
+            "zsh" => Some(Self::Zsh),
+            "fish" => Some(Self::Fish),
+            "node" | "nodejs" => Some(Self::Node),
+            _ if program.starts_with("python") => Some(Self::Python),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Interpreter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Self::Bash),
+            "sh" => Ok(Self::Sh),
+            "zsh" => Ok(Self::Zsh),
+            "fish" => Ok(Self::Fish),
+            "python" => Ok(Self::Python),
+            "node" => Ok(Self::Node),
+            _ => Err(format!("Unknown interpreter '{}' (expected bash, sh, zsh, fish, python or node)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Interpreter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Bash => "bash",
+            Self::Sh => "sh",
+            Self::Zsh => "zsh",
+            Self::Fish => "fish",
+            Self::Python => "python",
+            Self::Node => "node",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Decides whether `path` is a script and what runs it.
+///
+/// `.sh`, `.bash`, `.zsh` and `.fish` files always count; `.sh` files
+/// without a recognized shebang are run with bash, as magish always has. Any
+/// other file counts if it has a recognized shebang and is executable (on
+/// Windows, which has no exec bit, if it has no extension).
+pub fn detect(path: &Path) -> Option<Interpreter> {
+    let extension = path.extension().and_then(|s| s.to_str());
+    let is_sh = extension == Some("sh");
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    if by_extension(path).is_none() && !is_sh && !is_runnable(&metadata, extension.is_none()) {
+        return None;
+    }
+    read_shebang(path)
+        .as_deref()
+        .and_then(Interpreter::from_shebang)
+        .or_else(|| by_extension(path))
+        .or(is_sh.then_some(Interpreter::Bash))
+}
+
+/// The interpreter implied by a shell-specific extension. Plain `.sh` says
+/// nothing beyond "some shell", so it gives `None`.
+pub fn by_extension(path: &Path) -> Option<Interpreter> {
+    match path.extension().and_then(|s| s.to_str()) {
+        Some("bash") => Some(Interpreter::Bash),
+        Some("zsh") => Some(Interpreter::Zsh),
+        Some("fish") => Some(Interpreter::Fish),
+        _ => None,
+    }
+}
+
+/// The first line of a file without `#!`, if it starts with one.
+fn read_shebang(path: &Path) -> Option<String> {
+    let mut head = [0u8; 256];
+    let n = File::open(path).and_then(|mut file| file.read(&mut head)).ok()?;
+    let line = head[..n].split(|&b| b == b'\n').next()?;
+    let shebang = line.strip_prefix(b"#!")?;
+    Some(String::from_utf8_lossy(shebang).trim().to_string())
+}
+
+#[cfg(unix)]
+fn is_runnable(metadata: &fs::Metadata, _no_extension: bool) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_runnable(_metadata: &fs::Metadata, no_extension: bool) -> bool {
+    no_extension
+}
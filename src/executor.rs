@@ -0,0 +1,202 @@
+//! The programs that actually run scripts.
+//!
+//! `execute_script` only asks an `Executor` for processes to start; which
+//! program they run and how is up to the implementation. A new runner (a
+//! container, a remote host, ...) is a new `Executor`.
+
+use crate::detect::{self, Interpreter};
+use crate::metadata::ScriptInfo;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub trait Executor {
+    /// Shown in the run trace, e.g. `bash` or `wsl zsh`.
+    fn name(&self) -> String;
+
+    /// Whether scripts can be run command by command, which logging per
+    /// command, stepping and the failure policy rely on. If not, only
+    /// `script_command` is used.
+    fn runs_by_command(&self) -> bool {
+        false
+    }
+
+    /// A shell that reads commands from stdin, with `args` as its positional
+    /// parameters. It has to understand the POSIX syntax `ShellSession` sends.
+    fn session_command(&self, _args: &[String]) -> Option<Command> {
+        None
+    }
+
+    /// A process running the single command `code`, with `script` as `$0`
+    /// and `args` as the positional parameters.
+    fn line_command(&self, _code: &str, _script: &Path, _args: &[String]) -> Option<Command> {
+        None
+    }
+
+    /// A process running the whole script file with `args`.
+    fn script_command(&self, script: &Path, args: &[String]) -> Command;
+}
+
+/// bash, POSIX sh and zsh, which all accept `-s` and `-c` and the syntax of
+/// magish's session protocol.
+pub struct PosixShell {
+    program: &'static str,
+}
+
+impl PosixShell {
+    pub fn bash() -> Self {
+        Self { program: "bash" }
+    }
+
+    pub fn sh() -> Self {
+        Self { program: "sh" }
+    }
+
+    pub fn zsh() -> Self {
+        Self { program: "zsh" }
+    }
+}
+
+impl Executor for PosixShell {
+    fn name(&self) -> String {
+        self.program.to_string()
+    }
+
+    fn runs_by_command(&self) -> bool {
+        true
+    }
+
+    fn session_command(&self, args: &[String]) -> Option<Command> {
+        let mut command = Command::new(self.program);
+        command.arg("-s").arg("--").args(args);
+        Some(command)
+    }
+
+    fn line_command(&self, code: &str, script: &Path, args: &[String]) -> Option<Command> {
+        let mut command = Command::new(self.program);
+        command.arg("-c").arg(code).arg(script).args(args);
+        Some(command)
+    }
+
+    fn script_command(&self, script: &Path, args: &[String]) -> Command {
+        let mut command = Command::new(self.program);
+        command.arg(script).args(args);
+        command
+    }
+}
+
+/// fish doesn't speak POSIX sh, so its scripts always run as a whole.
+pub struct Fish;
+
+impl Executor for Fish {
+    fn name(&self) -> String {
+        "fish".to_string()
+    }
+
+    fn script_command(&self, script: &Path, args: &[String]) -> Command {
+        let mut command = Command::new("fish");
+        command.arg(script).args(args);
+        command
+    }
+}
+
+/// Any other interpreter that runs a script file given as its first argument,
+/// such as python or node.
+pub struct Interpreted {
+    program: &'static str,
+}
+
+impl Executor for Interpreted {
+    fn name(&self) -> String {
+        self.program.to_string()
+    }
+
+    fn script_command(&self, script: &Path, args: &[String]) -> Command {
+        let mut command = Command::new(self.program);
+        command.arg(script).args(args);
+        command
+    }
+}
+
+/// Runs another executor's processes inside WSL, translating Windows paths
+/// so the Linux side can find the script.
+pub struct Wsl {
+    inner: Box<dyn Executor>,
+}
+
+impl Wsl {
+    fn wrap(command: Command) -> Command {
+        let mut wsl = Command::new("wsl");
+        wsl.arg(command.get_program()).args(command.get_args());
+        wsl
+    }
+}
+
+impl Executor for Wsl {
+    fn name(&self) -> String {
+        format!("wsl {}", self.inner.name())
+    }
+
+    fn runs_by_command(&self) -> bool {
+        self.inner.runs_by_command()
+    }
+
+    fn session_command(&self, args: &[String]) -> Option<Command> {
+        self.inner.session_command(args).map(Self::wrap)
+    }
+
+    fn line_command(&self, code: &str, script: &Path, args: &[String]) -> Option<Command> {
+        self.inner.line_command(code, &to_wsl_path(script), args).map(Self::wrap)
+    }
+
+    fn script_command(&self, script: &Path, args: &[String]) -> Command {
+        Self::wrap(self.inner.script_command(&to_wsl_path(script), args))
+    }
+}
+
+/// Picks the executor for a script: an `@executor` tag in its header wins,
+/// then its shebang, then its extension, then `default`. On Windows shells
+/// run through WSL.
+pub fn for_script(script: &ScriptInfo, default: Interpreter) -> Box<dyn Executor> {
+    let interpreter = script
+        .executor
+        .or_else(|| script.shebang.as_deref().and_then(Interpreter::from_shebang))
+        .or_else(|| detect::by_extension(&script.path))
+        .unwrap_or(default);
+    let executor: Box<dyn Executor> = match interpreter {
+        Interpreter::Bash => Box::new(PosixShell::bash()),
+        Interpreter::Sh => Box::new(PosixShell::sh()),
+        Interpreter::Zsh => Box::new(PosixShell::zsh()),
+        Interpreter::Fish => Box::new(Fish),
+        Interpreter::Python => Box::new(Interpreted { program: interpreter.program() }),
+        Interpreter::Node => Box::new(Interpreted { program: interpreter.program() }),
+    };
+    if cfg!(target_os = "windows") && interpreter.is_shell() {
+        Box::new(Wsl { inner: executor })
+    } else {
+        executor
+    }
+}
+
+/// The executor for inline commands, such as a task's `run`: `default` if it
+/// is a POSIX shell, else bash.
+pub fn for_commands(default: Interpreter) -> Box<dyn Executor> {
+    let shell = match default {
+        Interpreter::Sh | Interpreter::Zsh => default,
+        _ => Interpreter::Bash,
+    };
+    let script = ScriptInfo { executor: Some(shell), ..ScriptInfo::default() };
+    for_script(&script, shell)
+}
+
+/// Maps `C:\dir\file` to `/mnt/c/dir/file`; other paths are only given
+/// forward slashes.
+fn to_wsl_path(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy().replace('\\', "/");
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(drive), Some(':')) if drive.is_ascii_alphabetic() => {
+            PathBuf::from(format!("/mnt/{}{}", drive.to_ascii_lowercase(), &text[2..]))
+        }
+        _ => PathBuf::from(text),
+    }
+}
@@ -0,0 +1,215 @@
+//! Directories visited at the prompt, ranked by how often and how recently
+//! they were visited, for `j`.
+
+use crate::config::Config;
+use crate::logs::now;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Once the visit counts add up to more than this, they are all scaled down
+/// and directories that drop below one visit are forgotten.
+const MAX_TOTAL_VISITS: f64 = 1000.0;
+
+/// How many matches `j -i` offers.
+pub const MAX_CHOICES: usize = 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirectoryEntry {
+    pub path: PathBuf,
+    /// Number of visits, scaled down as the database ages.
+    pub visits: f64,
+    /// Unix seconds of the latest visit.
+    pub last_visit: u64,
+}
+
+impl DirectoryEntry {
+    /// Visits weighted by how long ago the latest one was.
+    fn score(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.last_visit);
+        let weight = match age {
+            0..=3_599 => 4.0,
+            3_600..=86_399 => 2.0,
+            86_400..=604_799 => 0.5,
+            _ => 0.25,
+        };
+        self.visits * weight
+    }
+
+    /// Whether all `words` appear in the path in order, ignoring case, with
+    /// the last one in the final folder name.
+    fn matches(&self, words: &[String]) -> bool {
+        let path = self.path.to_string_lossy().to_lowercase();
+        let name = self.path.file_name().map_or(String::new(), |name| name.to_string_lossy().to_lowercase());
+        if words.last().is_some_and(|last| !name.contains(last.as_str())) {
+            return false;
+        }
+        let mut rest = path.as_str();
+        words.iter().all(|word| match rest.find(word.as_str()) {
+            Some(i) => {
+                rest = &rest[i + word.len()..];
+                true
+            }
+            None => false,
+        })
+    }
+}
+
+/// Directory visits, stored in `directories.json`.
+pub struct DirectoryDatabase {
+    entries: Vec<DirectoryEntry>,
+}
+
+impl DirectoryDatabase {
+    pub fn load() -> Self {
+        let entries = fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self { entries }
+    }
+
+    /// Counts a visit to `dir`, by its canonical path so that `/a/b/../c`
+    /// and `/a/c` are one directory.
+    pub fn record(dir: &Path) {
+        let dir = canonical(dir);
+        let mut database = Self::load();
+        let now = now();
+        match database.entries.iter_mut().find(|entry| entry.path == dir) {
+            Some(entry) => {
+                entry.visits += 1.0;
+                entry.last_visit = now;
+            }
+            None => database.entries.push(DirectoryEntry { path: dir, visits: 1.0, last_visit: now }),
+        }
+        database.age();
+        if let Err(e) = database.save() {
+            eprintln!("Failed to save directory history: {}", e);
+        }
+    }
+
+    /// Existing directories other than `current_dir` that match `pattern`,
+    /// best first. An empty pattern matches every directory.
+    pub fn matches(&self, pattern: &str, current_dir: &Path) -> Vec<&DirectoryEntry> {
+        let words: Vec<String> = pattern.split_whitespace().map(str::to_lowercase).collect();
+        let current_dir = canonical(current_dir);
+        let now = now();
+        let mut matches: Vec<&DirectoryEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.path != current_dir && entry.matches(&words) && entry.path.is_dir())
+            .collect();
+        matches.sort_by(|a, b| b.score(now).total_cmp(&a.score(now)));
+        matches
+    }
+
+    /// Scales the visit counts down once they grow past `MAX_TOTAL_VISITS`,
+    /// forgetting directories that were rarely visited or no longer exist.
+    fn age(&mut self) {
+        let total: f64 = self.entries.iter().map(|entry| entry.visits).sum();
+        if total <= MAX_TOTAL_VISITS {
+            return;
+        }
+        let factor = 0.9 * MAX_TOTAL_VISITS / total;
+        for entry in &mut self.entries {
+            entry.visits *= factor;
+        }
+        self.entries.retain(|entry| entry.visits >= 1.0 && entry.path.is_dir());
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        fs::create_dir_all(Config::state_dir())?;
+        let json = serde_json::to_string_pretty(&self.entries)?;
+        fs::write(Self::path(), json)
+    }
+
+    fn path() -> PathBuf {
+        Config::state_dir().join("directories.json")
+    }
+}
+
+/// `dir` with symlinks and `..` resolved, or as given if that fails.
+fn canonical(dir: &Path) -> PathBuf {
+    dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, visits: f64, last_visit: u64) -> DirectoryEntry {
+        DirectoryEntry { path: PathBuf::from(path), visits, last_visit }
+    }
+
+    fn words(pattern: &str) -> Vec<String> {
+        pattern.split_whitespace().map(str::to_lowercase).collect()
+    }
+
+    #[test]
+    fn entries_match_words_in_order_with_the_last_in_the_name() {
+        let entry = entry("/home/me/Projects/Web-Site", 1.0, 0);
+        assert!(entry.matches(&words("")));
+        assert!(entry.matches(&words("site")));
+        assert!(entry.matches(&words("proj web")));
+        assert!(entry.matches(&words("ME SITE")));
+        assert!(!entry.matches(&words("web proj")));
+        assert!(!entry.matches(&words("projects")));
+        assert!(!entry.matches(&words("home nope")));
+    }
+
+    #[test]
+    fn recent_visits_score_higher() {
+        let now = 10_000_000;
+        assert_eq!(entry("/a", 2.0, now).score(now), 8.0);
+        assert_eq!(entry("/a", 2.0, now - 7_200).score(now), 4.0);
+        assert_eq!(entry("/a", 2.0, now - 2 * 86_400).score(now), 1.0);
+        assert_eq!(entry("/a", 2.0, now - 30 * 86_400).score(now), 0.5);
+        // A visit in the future (clock changes) counts as recent.
+        assert_eq!(entry("/a", 2.0, now + 10).score(now), 8.0);
+    }
+
+    #[test]
+    fn age_scales_visits_and_forgets_rare_and_missing_directories() {
+        let dir = std::env::temp_dir().join(format!("magish-frecency-{}", std::process::id()));
+        fs::create_dir_all(dir.join("kept")).unwrap();
+        fs::create_dir_all(dir.join("rare")).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let mut database = DirectoryDatabase {
+            entries: vec![entry(&path("kept"), 1_500.0, 0), entry(&path("rare"), 1.0, 0), entry(&path("gone"), 500.0, 0)],
+        };
+        database.age();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(database.entries.len(), 1);
+        assert_eq!(database.entries[0].path, dir.join("kept"));
+        assert!((database.entries[0].visits - 1_500.0 * 0.9 * MAX_TOTAL_VISITS / 2_001.0).abs() < 1e-9);
+
+        let mut small = DirectoryDatabase { entries: vec![entry("/nowhere", 5.0, 0)] };
+        small.age();
+        assert_eq!(small.entries[0].visits, 5.0);
+    }
+
+    #[test]
+    fn database_matches_skip_the_current_and_missing_directories() {
+        let dir = std::env::temp_dir().join(format!("magish-frecency-matches-{}", std::process::id()));
+        for name in ["web", "web-old", "api"] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        let now = now();
+        let path = |name: &str| canonical(&dir.join(name)).to_string_lossy().into_owned();
+        let database = DirectoryDatabase {
+            entries: vec![
+                entry(&path("web-old"), 1.0, now),
+                entry(&path("web"), 5.0, now),
+                entry(&path("api"), 9.0, now),
+                entry(&format!("{}/web-gone", path("")), 9.0, now),
+            ],
+        };
+        let names = |current: &Path| -> Vec<String> {
+            database.matches("web", current).iter().map(|entry| entry.path.file_name().unwrap().to_string_lossy().into_owned()).collect()
+        };
+        assert_eq!(names(&dir), vec!["web", "web-old"]);
+        // The current directory is left out however it is spelled.
+        assert_eq!(names(&dir.join("api/../web")), vec!["web-old"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,763 @@
+//! Just enough of git to tell whether the scripts in a folder match HEAD,
+//! read straight from `.git` without running git or touching the network.
+
+use flate2::read::ZlibDecoder;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+type ObjectId = [u8; 20];
+
+/// Deepest chain of pack deltas followed before giving up; git itself
+/// writes chains of at most 50.
+const MAX_DELTA_DEPTH: usize = 50;
+
+/// Most bytes reserved up front from sizes a file claims, so a corrupt size
+/// can't exhaust memory before the data runs out.
+const MAX_PREALLOCATION: usize = 1 << 20;
+
+/// How a file in the work tree compares to HEAD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileState {
+    Clean,
+    /// Changed in the work tree or the index.
+    Modified,
+    /// Not in the index at all.
+    Untracked,
+}
+
+/// Branch and changes of the repository a folder belongs to.
+pub struct GitStatus {
+    /// The checked-out branch, or the short commit hash when HEAD is detached.
+    pub branch: Option<String>,
+    work_tree: PathBuf,
+    tracked: HashSet<String>,
+    changed: HashSet<String>,
+    /// Files in HEAD that were removed from the index.
+    staged_deletions: usize,
+}
+
+impl GitStatus {
+    /// Reads the status of the repository containing `dir`, if there is one.
+    pub fn load(dir: &Path) -> Option<Self> {
+        let repo = Repository::discover(dir)?;
+        let branch = repo.branch();
+        let index = repo.read_index().unwrap_or_default();
+        let mut changed = HashSet::new();
+        for entry in &index.entries {
+            if entry.stage != 0 || repo.worktree_differs(entry) {
+                changed.insert(entry.path.clone());
+            }
+        }
+
+        // The index's cached root tree matches HEAD after a commit, which
+        // saves reading HEAD's trees in the common case.
+        let head_tree = repo.head_tree();
+        let mut staged_deletions = 0;
+        if head_tree.is_none() || head_tree != index.root_tree {
+            let head = head_tree.and_then(|tree| repo.flatten_tree(&tree).ok()).unwrap_or_default();
+            for entry in &index.entries {
+                if head.get(&entry.path) != Some(&(entry.mode, entry.id)) {
+                    changed.insert(entry.path.clone());
+                }
+            }
+            let tracked: HashSet<&str> = index.entries.iter().map(|entry| entry.path.as_str()).collect();
+            staged_deletions = head.keys().filter(|path| !tracked.contains(path.as_str())).count();
+        }
+
+        Some(Self {
+            branch,
+            work_tree: repo.work_tree,
+            tracked: index.entries.into_iter().map(|entry| entry.path).collect(),
+            changed,
+            staged_deletions,
+        })
+    }
+
+    /// Whether anything tracked differs from HEAD. Untracked files don't count.
+    pub fn is_dirty(&self) -> bool {
+        !self.changed.is_empty() || self.staged_deletions > 0
+    }
+
+    pub fn file_state(&self, path: &Path) -> FileState {
+        let Some(relative) = path.strip_prefix(&self.work_tree).ok().and_then(index_path) else {
+            return FileState::Untracked;
+        };
+        if self.changed.contains(&relative) {
+            FileState::Modified
+        } else if self.tracked.contains(&relative) {
+            FileState::Clean
+        } else {
+            FileState::Untracked
+        }
+    }
+}
+
+/// The checked-out branch of the repository containing `dir`.
+pub fn current_branch(dir: &Path) -> Option<String> {
+    Repository::discover(dir)?.branch()
+}
+
+struct Repository {
+    work_tree: PathBuf,
+    /// Holds HEAD and the index; differs from `common_dir` in linked worktrees.
+    git_dir: PathBuf,
+    /// Holds objects and refs.
+    common_dir: PathBuf,
+}
+
+#[derive(Default)]
+struct Index {
+    entries: Vec<IndexEntry>,
+    /// Root of the index's cached tree, if it is up to date.
+    root_tree: Option<ObjectId>,
+}
+
+struct IndexEntry {
+    path: String,
+    id: ObjectId,
+    mode: u32,
+    stage: u16,
+    size: u32,
+    mtime: (u32, u32),
+}
+
+impl Repository {
+    /// Finds the repository for `dir`, following the `gitdir:` files used by
+    /// worktrees and submodules.
+    fn discover(dir: &Path) -> Option<Self> {
+        for ancestor in dir.ancestors() {
+            let candidate = ancestor.join(".git");
+            let git_dir = if candidate.is_dir() {
+                candidate
+            } else if candidate.is_file() {
+                let contents = fs::read_to_string(&candidate).ok()?;
+                ancestor.join(contents.trim().strip_prefix("gitdir:")?.trim())
+            } else {
+                continue;
+            };
+            let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+                Ok(common) => git_dir.join(common.trim()),
+                Err(_) => git_dir.clone(),
+            };
+            return Some(Self { work_tree: ancestor.to_path_buf(), git_dir, common_dir });
+        }
+        None
+    }
+
+    fn branch(&self) -> Option<String> {
+        let head = fs::read_to_string(self.git_dir.join("HEAD")).ok()?;
+        let head = head.trim();
+        match head.strip_prefix("ref: ") {
+            Some(reference) => Some(reference.strip_prefix("refs/heads/").unwrap_or(reference).to_string()),
+            None => Some(head.chars().take(7).collect()),
+        }
+    }
+
+    fn head_commit(&self) -> Option<ObjectId> {
+        let head = fs::read_to_string(self.git_dir.join("HEAD")).ok()?;
+        match head.trim().strip_prefix("ref: ") {
+            Some(reference) => self.resolve_ref(reference),
+            None => parse_hex(head.trim()),
+        }
+    }
+
+    fn resolve_ref(&self, reference: &str) -> Option<ObjectId> {
+        if let Ok(target) = fs::read_to_string(self.common_dir.join(reference)) {
+            return match target.trim().strip_prefix("ref: ") {
+                Some(next) => self.resolve_ref(next),
+                None => parse_hex(target.trim()),
+            };
+        }
+        let packed = fs::read_to_string(self.common_dir.join("packed-refs")).ok()?;
+        packed.lines().find_map(|line| {
+            let (id, name) = line.split_once(' ')?;
+            if name == reference {
+                parse_hex(id)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn head_tree(&self) -> Option<ObjectId> {
+        let (_, commit) = self.read_object(&self.head_commit()?).ok()?;
+        let first_line = commit.split(|&b| b == b'\n').next()?;
+        parse_hex(std::str::from_utf8(first_line).ok()?.strip_prefix("tree ")?)
+    }
+
+    /// Maps every file path in `tree` to its mode and blob id.
+    fn flatten_tree(&self, tree: &ObjectId) -> io::Result<HashMap<String, (u32, ObjectId)>> {
+        let mut files = HashMap::new();
+        let mut pending = vec![(String::new(), *tree)];
+        while let Some((prefix, id)) = pending.pop() {
+            let (_, data) = self.read_object(&id)?;
+            let mut rest = &data[..];
+            while let Some(space) = rest.iter().position(|&b| b == b' ') {
+                let nul = rest.iter().position(|&b| b == 0).ok_or_else(corrupt)?;
+                let mode = u32::from_str_radix(&String::from_utf8_lossy(&rest[..space]), 8).map_err(|_| corrupt())?;
+                let name = String::from_utf8_lossy(rest.get(space + 1..nul).ok_or_else(corrupt)?);
+                let id: ObjectId = rest.get(nul + 1..nul + 21).and_then(|id| id.try_into().ok()).ok_or_else(corrupt)?;
+                let path = format!("{}{}", prefix, name);
+                if mode == 0o40000 {
+                    pending.push((format!("{}/", path), id));
+                } else {
+                    files.insert(path, (mode, id));
+                }
+                rest = &rest[nul + 21..];
+            }
+        }
+        Ok(files)
+    }
+
+    /// Whether the work tree copy of an index entry has changed. Files whose
+    /// size and modification time match the index are assumed unchanged,
+    /// like `git status` does.
+    fn worktree_differs(&self, entry: &IndexEntry) -> bool {
+        const GITLINK: u32 = 0o160000;
+        const SYMLINK: u32 = 0o120000;
+        if entry.mode == GITLINK {
+            return false;
+        }
+        let path = self.work_tree.join(&entry.path);
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            return true;
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let executable = metadata.permissions().mode() & 0o111 != 0;
+            if entry.mode != SYMLINK && executable != (entry.mode == 0o100755) {
+                return true;
+            }
+        }
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|d| (d.as_secs() as u32, d.subsec_nanos()));
+        if metadata.len() == u64::from(entry.size) && mtime == Some(entry.mtime) {
+            return false;
+        }
+        let contents = if entry.mode == SYMLINK {
+            fs::read_link(&path).map(|target| target.to_string_lossy().into_owned().into_bytes())
+        } else {
+            fs::read(&path)
+        };
+        contents.map_or(true, |contents| hash_object("blob", &contents) != entry.id)
+    }
+
+    fn read_index(&self) -> io::Result<Index> {
+        parse_index(&fs::read(self.git_dir.join("index"))?)
+    }
+
+    /// Reads an object from the loose object store or a pack, returning its
+    /// type and contents.
+    fn read_object(&self, id: &ObjectId) -> io::Result<(String, Vec<u8>)> {
+        self.read_object_at_depth(id, 0)
+    }
+
+    /// `read_object` for the base of a delta `depth` deltas deep.
+    fn read_object_at_depth(&self, id: &ObjectId, depth: usize) -> io::Result<(String, Vec<u8>)> {
+        let hex = to_hex(id);
+        let objects = self.common_dir.join("objects");
+        if let Ok(file) = File::open(objects.join(&hex[..2]).join(&hex[2..])) {
+            let mut data = Vec::new();
+            ZlibDecoder::new(file).read_to_end(&mut data)?;
+            let nul = data.iter().position(|&b| b == 0).ok_or_else(corrupt)?;
+            let header = String::from_utf8_lossy(&data[..nul]).into_owned();
+            let kind = header.split(' ').next().unwrap_or_default().to_string();
+            return Ok((kind, data[nul + 1..].to_vec()));
+        }
+        for entry in fs::read_dir(objects.join("pack"))?.flatten() {
+            let idx_path = entry.path();
+            if idx_path.extension().and_then(|s| s.to_str()) != Some("idx") {
+                continue;
+            }
+            if let Some(offset) = find_in_pack_index(&idx_path, id)? {
+                let mut pack = File::open(idx_path.with_extension("pack"))?;
+                let (kind, data) = self.read_pack_entry(&mut pack, offset, depth)?;
+                let kind = ["", "commit", "tree", "blob", "tag"].get(kind as usize).copied().unwrap_or("unknown");
+                return Ok((kind.to_string(), data));
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("git object {} not found", hex)))
+    }
+
+    /// Reads the object at `offset` in a pack, applying deltas. `depth` is
+    /// how many deltas led here.
+    fn read_pack_entry(&self, pack: &mut File, offset: u64, depth: usize) -> io::Result<(u8, Vec<u8>)> {
+        const OFS_DELTA: u8 = 6;
+        const REF_DELTA: u8 = 7;
+        if depth > MAX_DELTA_DEPTH {
+            return Err(corrupt());
+        }
+        pack.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(&mut *pack);
+        let mut byte = read_byte(&mut reader)?;
+        let kind = (byte >> 4) & 0x7;
+        while byte & 0x80 != 0 {
+            byte = read_byte(&mut reader)?;
+        }
+        match kind {
+            OFS_DELTA => {
+                byte = read_byte(&mut reader)?;
+                let mut distance = u64::from(byte & 0x7f);
+                while byte & 0x80 != 0 {
+                    byte = read_byte(&mut reader)?;
+                    distance = next_varint(distance, byte)?;
+                }
+                // A base always comes before its delta in the pack.
+                if distance == 0 {
+                    return Err(corrupt());
+                }
+                let delta = inflate(reader)?;
+                let base_offset = offset.checked_sub(distance).ok_or_else(corrupt)?;
+                let (base_kind, base) = self.read_pack_entry(pack, base_offset, depth + 1)?;
+                Ok((base_kind, apply_delta(&base, &delta)?))
+            }
+            REF_DELTA => {
+                let mut base_id = [0u8; 20];
+                reader.read_exact(&mut base_id)?;
+                let delta = inflate(reader)?;
+                let (base_kind, base) = self.read_object_at_depth(&base_id, depth + 1)?;
+                let base_kind = ["", "commit", "tree", "blob", "tag"].iter().position(|k| *k == base_kind).unwrap_or(0);
+                Ok((base_kind as u8, apply_delta(&base, &delta)?))
+            }
+            _ => Ok((kind, inflate(reader)?)),
+        }
+    }
+}
+
+/// Parses an index file (versions 2 to 4).
+fn parse_index(data: &[u8]) -> io::Result<Index> {
+    if data.len() < 12 || &data[..4] != b"DIRC" {
+        return Err(corrupt());
+    }
+    let version = read_u32(data, 4)?;
+    let count = read_u32(data, 8)? as usize;
+    // Every entry takes at least 62 bytes, whatever the header claims.
+    let mut entries = Vec::with_capacity(count.min(data.len() / 62));
+    let mut pos = 12;
+    let mut previous_path = Vec::new();
+    for _ in 0..count {
+        let flags = read_u16(data, pos + 60)?;
+        let mut path_start = pos + 62;
+        if version >= 3 && flags & 0x4000 != 0 {
+            path_start += 2;
+        }
+        let (path, next) = if version >= 4 {
+            // Paths are stored as "drop N bytes of the previous path, then
+            // append this suffix".
+            let (strip, suffix_start) = read_varint(data, path_start)?;
+            let suffix = until_nul(data, suffix_start)?;
+            let keep = usize::try_from(strip).ok().and_then(|strip| previous_path.len().checked_sub(strip)).ok_or_else(corrupt)?;
+            let mut path = previous_path[..keep].to_vec();
+            path.extend_from_slice(suffix);
+            (path, suffix_start + suffix.len() + 1)
+        } else {
+            let path = until_nul(data, path_start)?;
+            let entry_len = path_start - pos + path.len();
+            (path.to_vec(), pos + (entry_len + 8) / 8 * 8)
+        };
+        entries.push(IndexEntry {
+            path: String::from_utf8_lossy(&path).into_owned(),
+            id: data.get(pos + 40..pos + 60).and_then(|id| id.try_into().ok()).ok_or_else(corrupt)?,
+            mode: read_u32(data, pos + 24)?,
+            stage: (flags >> 12) & 0x3,
+            size: read_u32(data, pos + 36)?,
+            mtime: (read_u32(data, pos + 8)?, read_u32(data, pos + 12)?),
+        });
+        previous_path = path;
+        pos = next;
+    }
+
+    // Extensions follow the entries; the last 20 bytes are a checksum.
+    let mut root_tree = None;
+    while pos + 8 <= data.len().saturating_sub(20) {
+        let len = read_u32(data, pos + 4)? as usize;
+        let body = data.get(pos + 8..(pos + 8).saturating_add(len)).ok_or_else(corrupt)?;
+        if &data[pos..pos + 4] == b"TREE" {
+            root_tree = parse_root_tree(body);
+        }
+        pos += 8 + len;
+    }
+    Ok(Index { entries, root_tree })
+}
+
+/// The bytes from `start` up to the next NUL.
+fn until_nul(data: &[u8], start: usize) -> io::Result<&[u8]> {
+    let rest = data.get(start..).ok_or_else(corrupt)?;
+    let len = rest.iter().position(|&b| b == 0).ok_or_else(corrupt)?;
+    Ok(&rest[..len])
+}
+
+/// Looks `id` up in a version 2 pack index and returns its offset in the pack.
+fn find_in_pack_index(path: &Path, id: &ObjectId) -> io::Result<Option<u64>> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; 8 + 256 * 4];
+    file.read_exact(&mut header)?;
+    if header[..8] != [0xff, b't', b'O', b'c', 0, 0, 0, 2] {
+        return Ok(None);
+    }
+    let fanout = |i: usize| read_u32(&header, 8 + i * 4);
+    let total = u64::from(fanout(255)?);
+    let mut low = if id[0] == 0 { 0 } else { u64::from(fanout(id[0] as usize - 1)?) };
+    let mut high = u64::from(fanout(id[0] as usize)?);
+    let ids_start = header.len() as u64;
+    let mut candidate = [0u8; 20];
+    while low < high {
+        let mid = (low + high) / 2;
+        file.seek(SeekFrom::Start(ids_start + mid * 20))?;
+        file.read_exact(&mut candidate)?;
+        match candidate.cmp(id) {
+            std::cmp::Ordering::Less => low = mid + 1,
+            std::cmp::Ordering::Greater => high = mid,
+            std::cmp::Ordering::Equal => {
+                // Skip the object ids and CRCs to reach the offset table.
+                let offsets_start = ids_start + total * 24;
+                let mut buf = [0u8; 8];
+                file.seek(SeekFrom::Start(offsets_start + mid * 4))?;
+                file.read_exact(&mut buf[..4])?;
+                let offset = read_u32(&buf, 0)?;
+                if offset & 0x8000_0000 == 0 {
+                    return Ok(Some(u64::from(offset)));
+                }
+                let large = u64::from(offset & 0x7fff_ffff);
+                file.seek(SeekFrom::Start(offsets_start + total * 4 + large * 8))?;
+                file.read_exact(&mut buf)?;
+                return Ok(Some(u64::from_be_bytes(buf)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Rebuilds an object from its base and a pack delta.
+fn apply_delta(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let (_, pos) = read_size(delta, 0)?;
+    let (target_len, mut pos) = read_size(delta, pos)?;
+    let target_len = usize::try_from(target_len).map_err(|_| corrupt())?;
+    let mut target = Vec::with_capacity(target_len.min(MAX_PREALLOCATION));
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        if op & 0x80 != 0 {
+            // Copy a range of the base; the bits of `op` say which bytes of
+            // the offset and size follow.
+            let mut offset = 0usize;
+            let mut size = 0usize;
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    offset |= usize::from(*delta.get(pos).ok_or_else(corrupt)?) << (8 * i);
+                    pos += 1;
+                }
+            }
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    size |= usize::from(*delta.get(pos).ok_or_else(corrupt)?) << (8 * i);
+                    pos += 1;
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+            target.extend_from_slice(base.get(offset..offset.saturating_add(size)).ok_or_else(corrupt)?);
+        } else if op != 0 {
+            let len = usize::from(op);
+            target.extend_from_slice(delta.get(pos..pos.saturating_add(len)).ok_or_else(corrupt)?);
+            pos += len;
+        } else {
+            return Err(corrupt());
+        }
+        if target.len() > target_len {
+            return Err(corrupt());
+        }
+    }
+    if target.len() != target_len {
+        return Err(corrupt());
+    }
+    Ok(target)
+}
+
+/// Reads the root entry of the index's `TREE` extension, unless it has been
+/// invalidated by changes to the index.
+fn parse_root_tree(body: &[u8]) -> Option<ObjectId> {
+    let nul = body.iter().position(|&b| b == 0)?;
+    if nul != 0 {
+        return None;
+    }
+    let newline = body.iter().position(|&b| b == b'\n')?;
+    let header = std::str::from_utf8(&body[1..newline]).ok()?;
+    let entry_count: i64 = header.split(' ').next()?.parse().ok()?;
+    if entry_count < 0 {
+        return None;
+    }
+    body.get(newline + 1..newline + 21)?.try_into().ok()
+}
+
+/// The object id git gives `contents` when stored as an object of `kind`.
+fn hash_object(kind: &str, contents: &[u8]) -> ObjectId {
+    let mut hasher = sha1_smol::Sha1::new();
+    hasher.update(format!("{} {}\0", kind, contents.len()).as_bytes());
+    hasher.update(contents);
+    hasher.digest().bytes()
+}
+
+/// Converts a path relative to the work tree to the form used in the index.
+fn index_path(relative: &Path) -> Option<String> {
+    let parts: Option<Vec<&str>> = relative.components().map(|c| c.as_os_str().to_str()).collect();
+    Some(parts?.join("/"))
+}
+
+fn inflate(reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    ZlibDecoder::new(reader).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn read_byte(reader: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_u16(data: &[u8], pos: usize) -> io::Result<u16> {
+    let bytes = data.get(pos..pos + 2).ok_or_else(corrupt)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> io::Result<u32> {
+    let bytes = data.get(pos..pos + 4).ok_or_else(corrupt)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// The offset-style variable length integer used by index v4 paths.
+fn read_varint(data: &[u8], mut pos: usize) -> io::Result<(u64, usize)> {
+    let mut byte = *data.get(pos).ok_or_else(corrupt)?;
+    let mut value = u64::from(byte & 0x7f);
+    while byte & 0x80 != 0 {
+        pos += 1;
+        byte = *data.get(pos).ok_or_else(corrupt)?;
+        value = next_varint(value, byte)?;
+    }
+    Ok((value, pos + 1))
+}
+
+/// Adds the next byte of an offset-style varint to `value`.
+fn next_varint(value: u64, byte: u8) -> io::Result<u64> {
+    value
+        .checked_add(1)
+        .filter(|value| value.leading_zeros() >= 7)
+        .map(|value| (value << 7) | u64::from(byte & 0x7f))
+        .ok_or_else(corrupt)
+}
+
+/// The little-endian size header at the start of a delta.
+fn read_size(data: &[u8], mut pos: usize) -> io::Result<(u64, usize)> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(pos).ok_or_else(corrupt)?;
+        pos += 1;
+        if shift > 63 {
+            return Err(corrupt());
+        }
+        value |= u64::from(byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok((value, pos));
+        }
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<ObjectId> {
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut id = [0u8; 20];
+    for (i, byte) in id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(id)
+}
+
+fn to_hex(id: &ObjectId) -> String {
+    id.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupt git data")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+
+    /// The fixed part of an index entry: mtime (7, 8), mode 100755, size 42,
+    /// object id 0x11…, and `flags`.
+    fn entry_header(flags: u16) -> Vec<u8> {
+        let mut entry = vec![0u8; 62];
+        entry[8..12].copy_from_slice(&7u32.to_be_bytes());
+        entry[12..16].copy_from_slice(&8u32.to_be_bytes());
+        entry[24..28].copy_from_slice(&0o100755u32.to_be_bytes());
+        entry[36..40].copy_from_slice(&42u32.to_be_bytes());
+        entry[40..60].copy_from_slice(&[0x11; 20]);
+        entry[60..62].copy_from_slice(&flags.to_be_bytes());
+        entry
+    }
+
+    fn index_file(version: u32, count: u32, entries: &[u8]) -> Vec<u8> {
+        let mut data = b"DIRC".to_vec();
+        data.extend_from_slice(&version.to_be_bytes());
+        data.extend_from_slice(&count.to_be_bytes());
+        data.extend_from_slice(entries);
+        data.extend_from_slice(&[0; 20]);
+        data
+    }
+
+    /// A version 2 or 3 entry, padded with NULs to a multiple of 8 bytes.
+    fn padded_entry(path: &str, extended: bool) -> Vec<u8> {
+        let mut entry = entry_header(path.len() as u16 | if extended { 0x4000 } else { 0 });
+        if extended {
+            entry.extend_from_slice(&[0, 0]);
+        }
+        entry.extend_from_slice(path.as_bytes());
+        let padded = (entry.len() + 8) / 8 * 8;
+        entry.resize(padded, 0);
+        entry
+    }
+
+    fn paths(index: &Index) -> Vec<&str> {
+        index.entries.iter().map(|entry| entry.path.as_str()).collect()
+    }
+
+    #[test]
+    fn parses_index_v2() {
+        let entries = [padded_entry("deploy.sh", false), padded_entry("scripts/build.sh", false)].concat();
+        let index = parse_index(&index_file(2, 2, &entries)).unwrap();
+        assert_eq!(paths(&index), ["deploy.sh", "scripts/build.sh"]);
+        let entry = &index.entries[0];
+        assert_eq!((entry.mode, entry.size, entry.mtime, entry.stage), (0o100755, 42, (7, 8), 0));
+        assert_eq!(entry.id, [0x11; 20]);
+        assert_eq!(index.root_tree, None);
+    }
+
+    #[test]
+    fn parses_index_v3_extended_flags() {
+        let entries = [padded_entry("a.sh", true), padded_entry("b.sh", false)].concat();
+        let index = parse_index(&index_file(3, 2, &entries)).unwrap();
+        assert_eq!(paths(&index), ["a.sh", "b.sh"]);
+    }
+
+    #[test]
+    fn parses_index_v4_prefix_compressed_paths() {
+        let mut entries = entry_header(0);
+        entries.extend_from_slice(b"\0scripts/build.sh\0");
+        entries.extend_from_slice(&entry_header(0));
+        // Drop "build.sh", keep "scripts/".
+        entries.extend_from_slice(b"\x08deploy.sh\0");
+        let index = parse_index(&index_file(4, 2, &entries)).unwrap();
+        assert_eq!(paths(&index), ["scripts/build.sh", "scripts/deploy.sh"]);
+    }
+
+    #[test]
+    fn reads_root_tree_extension() {
+        let mut entries = padded_entry("a.sh", false);
+        let body = [b"\x001 0\n".as_slice(), &[0x22; 20]].concat();
+        entries.extend_from_slice(b"TREE");
+        entries.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        entries.extend_from_slice(&body);
+        let index = parse_index(&index_file(2, 1, &entries)).unwrap();
+        assert_eq!(index.root_tree, Some([0x22; 20]));
+    }
+
+    #[test]
+    fn rejects_corrupt_indexes() {
+        // The extended flag moves the path past the end of the data.
+        let data = [b"DIRC".as_slice(), &3u32.to_be_bytes(), &1u32.to_be_bytes(), &entry_header(0x4000)].concat();
+        assert!(parse_index(&data).is_err());
+        // Far more entries claimed than there is data for.
+        assert!(parse_index(&index_file(2, u32::MAX, &padded_entry("a.sh", false))).is_err());
+        // A v4 path dropping more than the previous path had.
+        let entry = [entry_header(0).as_slice(), b"\x05a.sh\0"].concat();
+        assert!(parse_index(&index_file(4, 1, &entry)).is_err());
+        // An extension longer than the file.
+        let extension = [padded_entry("a.sh", false).as_slice(), b"TREE", &u32::MAX.to_be_bytes()].concat();
+        assert!(parse_index(&index_file(2, 1, &extension)).is_err());
+        assert!(parse_index(b"DIRC").is_err());
+    }
+
+    #[test]
+    fn applies_deltas() {
+        // Copy "hello", insert " there", copy " world".
+        let delta = [b"\x0b\x11\x90\x05\x06 there\x91\x05\x06".as_slice()].concat();
+        assert_eq!(apply_delta(b"hello world", &delta).unwrap(), b"hello there world");
+    }
+
+    #[test]
+    fn rejects_corrupt_deltas() {
+        // A huge target size with no data to back it.
+        assert!(apply_delta(b"base", b"\x04\xff\xff\xff\xff\x0f").is_err());
+        // A copy past the end of the base.
+        assert!(apply_delta(b"base", b"\x04\x08\x91\x02\x06").is_err());
+        // Fewer bytes than the target size says.
+        assert!(apply_delta(b"base", b"\x04\x05\x02ab").is_err());
+        // A size header that never ends.
+        assert!(apply_delta(b"base", &[0xff; 16]).is_err());
+        // Opcode 0 is reserved.
+        assert!(apply_delta(b"base", b"\x04\x01\x00").is_err());
+    }
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Reads the entry at `offset` of a pack made of `data`.
+    fn read_pack(name: &str, data: &[u8], offset: u64) -> io::Result<(u8, Vec<u8>)> {
+        let dir = std::env::temp_dir().join(format!("magish-git-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.pack");
+        fs::write(&path, data).unwrap();
+        let repo = Repository { work_tree: dir.clone(), git_dir: dir.clone(), common_dir: dir.clone() };
+        let result = repo.read_pack_entry(&mut File::open(&path).unwrap(), offset, 0);
+        let _ = fs::remove_dir_all(&dir);
+        result
+    }
+
+    #[test]
+    fn reads_offset_deltas_from_packs() {
+        // A blob "hello world", then a delta on it 1 + deflated bytes back.
+        let mut pack = [&[0x3b][..], &zlib(b"hello world")].concat();
+        let delta_offset = pack.len() as u64;
+        pack.extend_from_slice(&[0x60, delta_offset as u8]);
+        pack.extend_from_slice(&zlib(b"\x0b\x11\x90\x05\x06 there\x91\x05\x06"));
+        assert_eq!(read_pack("ofs", &pack, delta_offset).unwrap(), (3, b"hello there world".to_vec()));
+    }
+
+    #[test]
+    fn rejects_delta_loops_and_long_chains() {
+        // A delta that is its own base.
+        let pack = [&[0x60, 0x00][..], &zlib(b"")].concat();
+        assert!(read_pack("self", &pack, 0).is_err());
+
+        // Chains of deltas up to as long as git writes, and one longer.
+        let mut pack = [&[0x30][..], &zlib(b"")].concat();
+        let empty_delta = zlib(b"\x00\x00");
+        let mut offsets = Vec::new();
+        for _ in 0..=MAX_DELTA_DEPTH {
+            let base = offsets.last().copied().unwrap_or(0);
+            offsets.push(pack.len());
+            pack.extend_from_slice(&[0x60, (pack.len() - base) as u8]);
+            pack.extend_from_slice(&empty_delta);
+        }
+        assert!(read_pack("longest", &pack, offsets[MAX_DELTA_DEPTH - 1] as u64).is_ok());
+        assert!(read_pack("too-long", &pack, offsets[MAX_DELTA_DEPTH] as u64).is_err());
+    }
+}
@@ -1,38 +1,138 @@
+use crate::config::Config;
+use crate::detect;
+use crate::theme::Theme;
+use rustyline::completion::{Completer, Pair};
+use rustyline::config::Configurer;
 use rustyline::error::ReadlineError;
-use rustyline::DefaultEditor;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::{Hint, Hinter};
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::borrow::Cow;
 use std::path::{Path, PathBuf};
 use std::fs;
 
+/// Built-in prompt commands offered by tab completion.
+const COMMANDS: &[&str] = &[
+    "bookmark", "cd", "config", "dirs", "exit", "find", "go", "help", "history", "info", "j", "log", "logs", "ls", "popd", "preview",
+    "pushd", "quit", "rerun", "run", "runs", "scan", "sort", "step", "tasks",
+];
+
+/// Commands whose argument is always a directory.
+const DIR_COMMANDS: &[&str] = &["cd", "ls", "pushd"];
+
 pub struct CommandHistory {
-    editor: DefaultEditor,
+    editor: Editor<ReplHelper, DefaultHistory>,
     history_path: PathBuf,
+    exclude: Vec<String>,
 }
 
 impl CommandHistory {
-    pub fn new(_history_limit: usize) -> Self {
-        let mut editor = DefaultEditor::new().unwrap();
+    pub fn new(config: &Config) -> Self {
+        let editor_config = rustyline::Config::builder()
+            .max_history_size(config.history_limit)
+            .and_then(|builder| builder.history_ignore_dups(config.history_ignore_dups))
+            .map(|builder| builder.history_ignore_space(config.history_ignore_space).build())
+            .unwrap_or_default();
+        let mut editor = Editor::with_config(editor_config).unwrap();
+        editor.set_helper(Some(ReplHelper {
+            theme: config.theme.clone(),
+            ..ReplHelper::default()
+        }));
         let history_path = Self::get_history_path();
-        
+
         if history_path.exists() {
             let _ = editor.load_history(&history_path);
         }
-        
+
         Self {
             editor,
             history_path,
+            exclude: config.history_exclude.clone(),
+        }
+    }
+
+    /// Applies changed history and color settings.
+    pub fn apply(&mut self, config: &Config) {
+        let _ = self.editor.set_max_history_size(config.history_limit);
+        let _ = self.editor.set_history_ignore_dups(config.history_ignore_dups);
+        self.editor.set_history_ignore_space(config.history_ignore_space);
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.theme = config.theme.clone();
         }
+        self.exclude = config.history_exclude.clone();
     }
 
-    pub fn readline(&mut self, prompt: &str) -> Result<String, ReadlineError> {
-        match self.editor.readline(prompt) {
-            Ok(line) => {
+    /// Reads a line, expanding `!!` and `!n` from the history. Lines that are
+    /// excluded in the config are not added to the history.
+    ///
+    /// Paths are completed relative to `current_dir`, and a script number
+    /// typed on its own is hinted with the matching entry of `scripts`.
+    pub fn readline(&mut self, prompt: &str, current_dir: &Path, scripts: &[PathBuf]) -> Result<String, ReadlineError> {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.current_dir = current_dir.to_path_buf();
+            helper.scripts = scripts.to_vec();
+        }
+        loop {
+            let line = self.editor.readline(prompt)?;
+            let line = match self.expand(&line) {
+                Ok(Some(expanded)) => {
+                    println!("{}", expanded);
+                    expanded
+                }
+                Ok(None) => line,
+                Err(message) => {
+                    println!("{}", message);
+                    continue;
+                }
+            };
+            if !self.exclude.iter().any(|excluded| excluded == line.trim()) {
                 let _ = self.editor.add_history_entry(line.as_str());
-                Ok(line)
             }
-            Err(err) => Err(err),
+            return Ok(line);
         }
     }
 
+    /// Resolves `!!` (the last entry) and `!n` (entry `n` as numbered by
+    /// `history`). Returns `None` for lines that aren't history references.
+    fn expand(&self, line: &str) -> Result<Option<String>, String> {
+        let line = line.trim();
+        let entries = self.entries();
+        let index = match line.strip_prefix('!') {
+            Some("!") => entries.len().checked_sub(1),
+            Some(n) => match n.parse::<usize>() {
+                Ok(n) => n.checked_sub(1),
+                Err(_) => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        index
+            .and_then(|index| entries.get(index).cloned())
+            .map(Some)
+            .ok_or_else(|| format!("{}: event not found", line))
+    }
+
+    pub fn entries(&self) -> Vec<String> {
+        self.editor.history().iter().cloned().collect()
+    }
+
+    /// Prints the history numbered for `!n`, keeping only entries that
+    /// contain `pattern` if one is given.
+    pub fn print(&self, pattern: Option<&str>) {
+        let pattern = pattern.map(str::to_lowercase);
+        for (i, entry) in self.entries().iter().enumerate() {
+            if pattern.as_ref().is_none_or(|p| entry.to_lowercase().contains(p)) {
+                println!("{:>5}  {}", i + 1, entry);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) -> Result<(), ReadlineError> {
+        self.editor.clear_history()?;
+        self.save_history()
+    }
+
     pub fn save_history(&mut self) -> Result<(), ReadlineError> {
         if let Some(parent) = self.history_path.parent() {
             let _ = fs::create_dir_all(parent);
@@ -41,7 +141,122 @@ impl CommandHistory {
     }
 
     fn get_history_path() -> PathBuf {
-        let exe_path = std::env::current_exe().unwrap_or_else(|_| PathBuf::from("."));
-        exe_path.parent().unwrap_or(Path::new(".")).join("magish-history.txt")
+        Config::state_dir().join("magish-history.txt")
+    }
+}
+
+/// Tab completion and inline hints for the prompt.
+#[derive(Default)]
+struct ReplHelper {
+    current_dir: PathBuf,
+    scripts: Vec<PathBuf>,
+    theme: Theme,
+}
+
+impl ReplHelper {
+    /// Completes `word` as a path relative to the prompt's folder, listing
+    /// scripts first, then folders, then everything else.
+    fn complete_path(&self, word: &str, dirs_only: bool) -> Vec<Pair> {
+        let (dir_part, prefix) = match word.rfind('/') {
+            Some(i) => (&word[..=i], &word[i + 1..]),
+            None => ("", word),
+        };
+        let base = if Path::new(dir_part).is_absolute() {
+            PathBuf::from(dir_part)
+        } else {
+            self.current_dir.join(dir_part)
+        };
+        let pattern = format!("{}*", glob::Pattern::escape(&base.join(prefix).to_string_lossy()));
+        let options = glob::MatchOptions {
+            require_literal_leading_dot: !prefix.starts_with('.'),
+            ..Default::default()
+        };
+        let mut candidates: Vec<(u8, Pair)> = glob::glob_with(&pattern, options)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|path| {
+                let name = path.file_name()?.to_string_lossy().into_owned();
+                let (rank, suffix) = if path.is_dir() {
+                    (1, "/")
+                } else if dirs_only {
+                    return None;
+                } else if detect::detect(&path).is_some() {
+                    (0, "")
+                } else {
+                    (2, "")
+                };
+                let display = format!("{}{}", name, suffix);
+                Some((rank, Pair { replacement: format!("{}{}", dir_part, display), display }))
+            })
+            .collect();
+        candidates.sort_by(|(a, x), (b, y)| a.cmp(b).then_with(|| x.display.cmp(&y.display)));
+        candidates.into_iter().map(|(_, pair)| pair).collect()
     }
 }
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before = &line[..pos];
+        let start = before.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &before[start..];
+        if start == 0 {
+            // The first word is a command, or a path to navigate to or run.
+            let mut candidates: Vec<Pair> = COMMANDS
+                .iter()
+                .filter(|command| command.starts_with(word))
+                .map(|command| Pair { display: command.to_string(), replacement: format!("{} ", command) })
+                .collect();
+            candidates.extend(self.complete_path(word, false));
+            return Ok((0, candidates));
+        }
+        let command = before.split_whitespace().next().unwrap_or_default();
+        Ok((start, self.complete_path(word, DIR_COMMANDS.contains(&command))))
+    }
+}
+
+/// An inline hint; only command hints can be accepted with the right arrow.
+struct ReplHint {
+    display: String,
+    completion: Option<String>,
+}
+
+impl Hint for ReplHint {
+    fn display(&self) -> &str {
+        &self.display
+    }
+
+    fn completion(&self) -> Option<&str> {
+        self.completion.as_deref()
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = ReplHint;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<ReplHint> {
+        if pos < line.len() || line.is_empty() {
+            return None;
+        }
+        if let Ok(num) = line.trim().parse::<usize>() {
+            let script = self.scripts.get(num.checked_sub(1)?)?;
+            let name = script.file_name()?.to_string_lossy();
+            return Some(ReplHint { display: format!("  -> {}", name), completion: None });
+        }
+        let rest = COMMANDS.iter().find(|command| command.starts_with(line) && command.len() > line.len())?;
+        let rest = &rest[line.len()..];
+        Some(ReplHint { display: rest.to_string(), completion: Some(rest.to_string()) })
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(self.theme.paint(&self.theme.hint, hint))
+    }
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
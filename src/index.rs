@@ -0,0 +1,124 @@
+use crate::config::Config;
+use crate::logs::now;
+use crate::metadata::ScriptInfo;
+use crate::runs;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/// Held while the index is written; false once `close` has been called.
+static SAVING: Mutex<bool> = Mutex::new(true);
+
+/// What `scan` found last time, stored in `script-index.json` so the next
+/// scan can show results at once and only re-read folders that changed.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ScriptIndex {
+    pub updated_at: u64,
+    /// Every folder a scan has read, with what it found there.
+    pub folders: HashMap<PathBuf, IndexedFolder>,
+    pub scripts: BTreeMap<PathBuf, IndexedScript>,
+}
+
+/// A folder's direct children as of its modification time. Adding, removing
+/// or renaming an entry changes that time, so an unchanged time means the
+/// lists are still right.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct IndexedFolder {
+    /// Seconds and nanoseconds since the Unix epoch.
+    pub modified: Option<(u64, u32)>,
+    pub subfolders: Vec<String>,
+    pub scripts: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct IndexedScript {
+    #[serde(flatten)]
+    pub info: ScriptInfo,
+    pub content_hash: String,
+}
+
+impl IndexedScript {
+    pub fn read(path: &Path) -> Self {
+        let content_hash = fs::read_to_string(path).map(|contents| runs::content_hash(&contents)).unwrap_or_default();
+        Self { info: ScriptInfo::read(path), content_hash }
+    }
+
+    /// Whether the file still has the size and modification time it was
+    /// indexed with.
+    pub fn is_current(&self) -> bool {
+        let Ok(metadata) = fs::metadata(&self.info.path) else {
+            return false;
+        };
+        let modified = metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs());
+        metadata.len() == self.info.size && modified == self.info.modified
+    }
+}
+
+impl ScriptIndex {
+    /// The saved index, or `None` if there is none yet.
+    pub fn load() -> Option<Self> {
+        let json = fs::read_to_string(Self::path()).ok()?;
+        serde_json::from_str(&json).ok()
+    }
+
+    /// Writes the index to a temporary file and renames it into place, so
+    /// readers never see half of it.
+    pub fn save(&mut self) -> std::io::Result<()> {
+        let open = SAVING.lock().unwrap_or_else(|e| e.into_inner());
+        if !*open {
+            return Ok(());
+        }
+        self.updated_at = now();
+        fs::create_dir_all(Config::state_dir())?;
+        let json = serde_json::to_string(self)?;
+        let temp = Self::path().with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&temp, json)?;
+        fs::rename(&temp, Self::path()).inspect_err(|_| {
+            let _ = fs::remove_file(&temp);
+        })
+    }
+
+    /// Waits for a save in progress and skips later ones, so a background
+    /// refresh still running when magish exits leaves the saved index alone.
+    pub fn close() {
+        *SAVING.lock().unwrap_or_else(|e| e.into_inner()) = false;
+    }
+
+    /// Indexed scripts below `roots`, at most `max_depth` folders down.
+    pub fn scripts_under(&self, roots: &[PathBuf], max_depth: Option<usize>) -> Vec<IndexedScript> {
+        self.scripts
+            .iter()
+            .filter(|(path, _)| {
+                roots.iter().any(|root| {
+                    path.strip_prefix(root).is_ok_and(|relative| {
+                        max_depth.is_none_or(|max| relative.components().count() <= max + 1)
+                    })
+                })
+            })
+            .map(|(_, script)| script.clone())
+            .collect()
+    }
+
+    /// Replaces what the index knows about everything below `roots`, at
+    /// most `max_depth` folders down, with the results of a new scan of
+    /// them. Deeper folders keep what earlier scans found.
+    pub fn replace(&mut self, roots: &[PathBuf], max_depth: Option<usize>, folders: HashMap<PathBuf, IndexedFolder>, scripts: Vec<IndexedScript>) {
+        // Folders `max_depth` down were read; scripts are one level below.
+        let within = |path: &Path, extra: usize| {
+            roots.iter().any(|root| {
+                path.strip_prefix(root).is_ok_and(|relative| max_depth.is_none_or(|max| relative.components().count() <= max + extra))
+            })
+        };
+        self.folders.retain(|dir, _| !within(dir, 0) && !folders.contains_key(dir));
+        self.scripts.retain(|path, _| !within(path, 1));
+        self.folders.extend(folders);
+        self.scripts.extend(scripts.into_iter().map(|script| (script.info.path.clone(), script)));
+    }
+
+    fn path() -> PathBuf {
+        Config::state_dir().join("script-index.json")
+    }
+}
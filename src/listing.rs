@@ -0,0 +1,147 @@
+use crate::config::{Config, ScriptSort};
+use crate::detect;
+use crate::metadata::ScriptInfo;
+use crate::runs::RunDatabase;
+use crate::scan::{self, IgnoreRule};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Hands out script numbers that stay put for the whole session.
+///
+/// The first listing of a folder fixes its order. Later listings keep that
+/// order, drop scripts that were deleted and append new ones at the end, so
+/// a number typed from an older listing still picks the same script.
+#[derive(Default)]
+pub struct ScriptNumbers {
+    folders: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl ScriptNumbers {
+    pub fn scripts(&mut self, dir: &Path, config: &Config) -> Vec<PathBuf> {
+        let current = list_scripts(dir, config);
+        let Some(numbered) = self.folders.get_mut(dir) else {
+            self.folders.insert(dir.to_path_buf(), current.clone());
+            return current;
+        };
+        numbered.retain(|script| current.contains(script));
+        for script in current {
+            if !numbered.contains(&script) {
+                numbered.push(script);
+            }
+        }
+        numbered.clone()
+    }
+
+    /// Forgets the session's numbering, e.g. after the sort order changed.
+    pub fn reset(&mut self) {
+        self.folders.clear();
+    }
+}
+
+/// The scripts in `dir` and, up to `config.list_depth` levels down, its
+/// subfolders, sorted by `config.script_sort`.
+pub fn list_scripts(dir: &Path, config: &Config) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    collect_scripts(dir, config.list_depth, &scan::exclude_rules(&config.scan_exclude), &mut files);
+    sort_by_name(dir, &mut files);
+    match config.script_sort {
+        ScriptSort::Name => {}
+        ScriptSort::Modified => {
+            // Newest first; the stable sort keeps name order for ties.
+            files.sort_by_key(|file| {
+                std::cmp::Reverse(fs::metadata(file).and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH))
+            });
+        }
+        ScriptSort::Usage => {
+            let counts = RunDatabase::load().run_counts();
+            files.sort_by_key(|file| std::cmp::Reverse(counts.get(file).copied().unwrap_or(0)));
+        }
+    }
+    files
+}
+
+/// Fuzzy-matches `pattern` against the names (relative to `dir`) and
+/// descriptions of `scripts`, best matches first.
+pub fn find_scripts(dir: &Path, pattern: &str, mut scripts: Vec<ScriptInfo>) -> Vec<ScriptInfo> {
+    scripts.sort_by_cached_key(|info| info.path.strip_prefix(dir).unwrap_or(&info.path).to_string_lossy().to_lowercase());
+    let mut matches: Vec<(i64, ScriptInfo)> = scripts
+        .into_iter()
+        .filter_map(|info| {
+            let relative = info.path.strip_prefix(dir).unwrap_or(&info.path).to_string_lossy().into_owned();
+            // A match in the name counts for more than one in the description.
+            let name_score = fuzzy_score(pattern, &relative).map(|score| score * 2);
+            let description_score = info.description.as_deref().and_then(|description| fuzzy_score(pattern, description));
+            let score = name_score.max(description_score)?;
+            Some((score, info))
+        })
+        .collect();
+    matches.sort_by(|(a, _), (b, _)| b.cmp(a));
+    matches.into_iter().map(|(_, info)| info).collect()
+}
+
+/// Scripts in `dir` and `depth` levels of its subfolders, leaving out hidden
+/// folders and whatever the `scan_exclude` rules match, like `scan` does.
+fn collect_scripts(dir: &Path, depth: usize, exclude: &[Arc<IgnoreRule>], files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // Symlinked folders are not followed, so loops can't trap the walk.
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if scan::is_ignored(exclude, &path, &name, file_type.is_dir()) {
+            continue;
+        }
+        if file_type.is_dir() {
+            if depth > 0 && !name.starts_with('.') {
+                collect_scripts(&path, depth - 1, exclude, files);
+            }
+        } else if detect::detect(&path).is_some() {
+            files.push(path);
+        }
+    }
+}
+
+/// Sorts by path relative to `dir`, so scripts in `dir` itself come before
+/// those in subfolders with a later name.
+fn sort_by_name(dir: &Path, files: &mut [PathBuf]) {
+    files.sort_by_cached_key(|file| file.strip_prefix(dir).unwrap_or(file).to_string_lossy().to_lowercase());
+}
+
+/// Scores how well `pattern` matches `text` when its characters appear in
+/// order (case-insensitively), or `None` if they don't. Runs of consecutive
+/// characters and matches at word starts score higher.
+fn fuzzy_score(pattern: &str, text: &str) -> Option<i64> {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().filter(|c| !c.is_whitespace()).collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    if pattern.is_empty() {
+        return Some(0);
+    }
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+    for (i, &c) in text.iter().enumerate() {
+        if c != pattern[next] {
+            continue;
+        }
+        score += 1;
+        if previous == Some(i.wrapping_sub(1)) {
+            score += 5;
+        }
+        if i == 0 || matches!(text[i - 1], '/' | '_' | '-' | '.' | ' ') {
+            score += 3;
+        }
+        previous = Some(i);
+        next += 1;
+        if next == pattern.len() {
+            return Some(score);
+        }
+    }
+    None
+}
@@ -0,0 +1,346 @@
+use crate::config::Config;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Metadata of one script run, stored as `run.json` in the run's log folder.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RunRecord {
+    pub id: u64,
+    pub script: PathBuf,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    pub exit_status: Option<i32>,
+    /// Line of the command that made the run fail, if it failed.
+    #[serde(default)]
+    pub failed_line: Option<usize>,
+    pub commands: Vec<CommandRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CommandRecord {
+    pub line: usize,
+    pub command: String,
+    pub exit_status: i32,
+    pub duration_ms: u64,
+}
+
+/// How many runs the logs folder keeps before deleting the oldest.
+const MAX_LOGS: usize = 200;
+
+/// Copies script output to the terminal and, if logging, to a log file.
+#[derive(Clone)]
+pub struct Tee {
+    file: Option<Arc<Mutex<File>>>,
+    is_stderr: bool,
+}
+
+impl Tee {
+    /// Where a script should write: a pipe to copy from when logging, else
+    /// magish's own stream, so the script keeps the terminal.
+    pub fn stdio(&self) -> Stdio {
+        if self.file.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        }
+    }
+
+    pub fn write(&self, bytes: &[u8]) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        if self.is_stderr {
+            let mut err = io::stderr();
+            err.write_all(bytes)?;
+            err.flush()?;
+        } else {
+            let mut out = io::stdout();
+            out.write_all(bytes)?;
+            out.flush()?;
+        }
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                file.write_all(bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forwards everything from `reader` on a background thread until EOF.
+    pub fn spawn_copy(&self, mut reader: impl Read + Send + 'static) -> JoinHandle<()> {
+        let tee = self.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok(n) = reader.read(&mut buf) {
+                if n == 0 || tee.write(&buf[..n]).is_err() {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+/// The log of the script run in progress.
+pub struct RunLog {
+    dir: Option<PathBuf>,
+    record: RunRecord,
+    stdout: Tee,
+    stderr: Tee,
+}
+
+impl RunLog {
+    /// Starts a new log in the logs folder, capturing the script's output if
+    /// `capture_output`. If the folder can't be written the run still goes
+    /// ahead, with output only going to the terminal. The oldest logs are
+    /// deleted once there are more than `MAX_LOGS`.
+    pub fn create(script: &Path, capture_output: bool) -> Self {
+        let ids = log_ids();
+        if ids.len() >= MAX_LOGS {
+            for id in &ids[..=ids.len() - MAX_LOGS] {
+                let _ = fs::remove_dir_all(logs_dir().join(id.to_string()));
+            }
+        }
+        let mut id = ids.last().map_or(1, |id| id + 1);
+        let mut dir = logs_dir().join(id.to_string());
+        // Creating the folder claims the id; tasks running in parallel may
+        // have taken it first.
+        let files = fs::create_dir_all(logs_dir())
+            .and_then(|_| loop {
+                match fs::create_dir(&dir) {
+                    Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                        id += 1;
+                        dir = logs_dir().join(id.to_string());
+                    }
+                    result => break result,
+                }
+            })
+            .and_then(|_| {
+                if !capture_output {
+                    return Ok(None);
+                }
+                Ok(Some((File::create(dir.join("stdout.log"))?, File::create(dir.join("stderr.log"))?)))
+            });
+        let (dir, stdout, stderr) = match files {
+            Ok(Some((stdout, stderr))) => (Some(dir), Some(stdout), Some(stderr)),
+            Ok(None) => (Some(dir), None, None),
+            Err(e) => {
+                eprintln!("Failed to create run log: {}", e);
+                (None, None, None)
+            }
+        };
+        let log = Self {
+            dir,
+            record: RunRecord {
+                id,
+                script: script.to_path_buf(),
+                started_at: now(),
+                finished_at: None,
+                exit_status: None,
+                failed_line: None,
+                commands: Vec::new(),
+            },
+            stdout: Tee { file: stdout.map(|f| Arc::new(Mutex::new(f))), is_stderr: false },
+            stderr: Tee { file: stderr.map(|f| Arc::new(Mutex::new(f))), is_stderr: true },
+        };
+        log.save();
+        log
+    }
+
+    pub fn id(&self) -> u64 {
+        self.record.id
+    }
+
+    pub fn stdout(&self) -> Tee {
+        self.stdout.clone()
+    }
+
+    pub fn stderr(&self) -> Tee {
+        self.stderr.clone()
+    }
+
+    pub fn record_command(&mut self, line: usize, command: &str, exit_status: i32, duration_ms: u64) {
+        self.record.commands.push(CommandRecord {
+            line,
+            command: command.to_string(),
+            exit_status,
+            duration_ms,
+        });
+        self.save();
+    }
+
+    pub fn set_failed_line(&mut self, line: usize) {
+        self.record.failed_line = Some(line);
+    }
+
+    pub fn finish(mut self, exit_status: i32) -> RunRecord {
+        self.record.finished_at = Some(now());
+        self.record.exit_status = Some(exit_status);
+        if exit_status == 0 {
+            self.record.failed_line = None;
+        }
+        self.save();
+        self.record
+    }
+
+    fn save(&self) {
+        let Some(dir) = &self.dir else {
+            return;
+        };
+        let result = serde_json::to_string_pretty(&self.record)
+            .map_err(io::Error::from)
+            .and_then(|json| fs::write(dir.join("run.json"), json));
+        if let Err(e) = result {
+            eprintln!("Failed to save run log: {}", e);
+        }
+    }
+}
+
+pub fn logs_dir() -> PathBuf {
+    Config::state_dir().join("logs")
+}
+
+/// Ids of the logged runs, oldest first.
+fn log_ids() -> Vec<u64> {
+    let mut ids: Vec<u64> = fs::read_dir(logs_dir())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .collect();
+    ids.sort_unstable();
+    ids
+}
+
+/// All recorded runs, oldest first.
+pub fn list_runs() -> Vec<RunRecord> {
+    let mut runs: Vec<RunRecord> = fs::read_dir(logs_dir())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| fs::read_to_string(entry.path().join("run.json")).ok())
+        .filter_map(|json| serde_json::from_str(&json).ok())
+        .collect();
+    runs.sort_by_key(|run| run.id);
+    runs
+}
+
+pub fn load_run(id: u64) -> Option<RunRecord> {
+    let json = fs::read_to_string(logs_dir().join(id.to_string()).join("run.json")).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+/// Reads a run's captured `stdout.log` or `stderr.log`.
+pub fn read_output(id: u64, file_name: &str) -> String {
+    fs::read_to_string(logs_dir().join(id.to_string()).join(file_name)).unwrap_or_default()
+}
+
+/// Prints one line per recorded run for the `logs` command.
+pub fn print_run_list() {
+    let runs = list_runs();
+    if runs.is_empty() {
+        println!("No runs logged yet.");
+        return;
+    }
+    println!("{:>5}  {:<19}  {:>6}  {:>9}  Script", "Id", "Started (UTC)", "Status", "Duration");
+    for run in runs {
+        let status = run.exit_status.map_or("-".to_string(), |code| code.to_string());
+        let duration = run.finished_at.map_or("-".to_string(), |end| format!("{}s", end.saturating_sub(run.started_at)));
+        println!(
+            "{:>5}  {}  {:>6}  {:>9}  {}",
+            run.id,
+            format_timestamp(run.started_at),
+            status,
+            duration,
+            run.script.display()
+        );
+    }
+}
+
+/// Prints a run's commands and its captured output for `log show <id>`.
+pub fn print_run(id: u64) {
+    let Some(run) = load_run(id) else {
+        println!("No run with id {}", id);
+        return;
+    };
+    println!("Run #{}: {}", run.id, run.script.display());
+    println!("Started:  {} UTC", format_timestamp(run.started_at));
+    if let Some(finished_at) = run.finished_at {
+        println!("Finished: {} UTC", format_timestamp(finished_at));
+    }
+    match (run.exit_status, run.failed_line) {
+        (Some(code), Some(line)) => println!("Status:   {} (failed at line {})", code, line),
+        (Some(code), None) => println!("Status:   {}", code),
+        (None, _) => println!("Status:   unfinished"),
+    }
+    println!("\nCommands:");
+    for command in &run.commands {
+        println!(
+            "  line {:>4}  exit {:>3}  {:>7}ms  {}",
+            command.line,
+            command.exit_status,
+            command.duration_ms,
+            command.command.lines().next().unwrap_or_default()
+        );
+    }
+    for (title, file_name) in [("stdout", "stdout.log"), ("stderr", "stderr.log")] {
+        let output = read_output(id, file_name);
+        if !output.is_empty() {
+            println!("\n--- {} ---", title);
+            print!("{}", output);
+            if !output.ends_with('\n') {
+                println!();
+            }
+        }
+    }
+}
+
+/// Prints the last `count` lines of a run's output (the latest run by default).
+pub fn print_tail(id: Option<u64>, count: usize) {
+    let Some(id) = id.or_else(|| log_ids().last().copied()) else {
+        println!("No runs logged yet.");
+        return;
+    };
+    for (title, file_name) in [("stdout", "stdout.log"), ("stderr", "stderr.log")] {
+        let output = read_output(id, file_name);
+        let lines: Vec<&str> = output.lines().collect();
+        println!("--- run #{} {} (last {} lines) ---", id, title, count.min(lines.len()));
+        for line in &lines[lines.len().saturating_sub(count)..] {
+            println!("{}", line);
+        }
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Formats Unix seconds as `YYYY-MM-DD HH:MM:SS` (UTC).
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
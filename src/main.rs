@@ -3,100 +3,220 @@
 //! Features:
 //! - Detects OS and on Windows ensures WSL2 is available (or directs to install it).
 //! - Prompts for a Bash script path, or auto-discovers `.sh` files in current directory.
-//! - Executes each line of the script in Bash/WSL sequentially.
+//! - Executes the script's commands in one Bash/WSL session (or one process per line).
 //! - Retry up to 3 times for missing scripts, then exits.
 
+mod cli;
 mod config;
+mod detect;
+mod executor;
+mod frecency;
+mod git;
 mod history;
+mod index;
+mod listing;
+mod logs;
+mod metadata;
+mod navigation;
+mod parser;
+mod prompt;
+mod runs;
+mod scan;
+mod session;
+mod stepper;
+mod tasks;
+mod theme;
 
-use config::Config;
+use clap::Parser;
+use cli::Cli;
+use config::{Config, ExecutionMode, FailurePolicy, ScriptSort};
+use detect::Interpreter;
+use executor::Executor;
+use frecency::DirectoryDatabase;
+use git::{FileState, GitStatus};
 use history::CommandHistory;
+use listing::ScriptNumbers;
+use logs::RunLog;
+use metadata::ScriptInfo;
+use navigation::Navigation;
+use parser::Statement;
+use prompt::PromptContext;
+use runs::{RunDatabase, RunEntry};
+use scan::{ExportFormat, ScanOptions};
+use session::ShellSession;
+use stepper::{StepAction, Stepper};
+use tasks::TaskFile;
+use theme::Theme;
 use std::fs;
-use std::io;
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::Command;
 use std::thread;
-use std::time::Duration;
-use std::collections::HashSet;
+use std::time::{Duration, Instant};
 
 fn main() {
-    println!(r#"
- ██████   ██████                     ███   █████████  █████   █████
-░░██████ ██████                     ░░░   ███░░░░░███░░███   ░░███ 
- ░███░█████░███   ██████    ███████ ████ ░███    ░░░  ░███    ░███ 
- ░███░░███ ░███  ░░░░░███  ███░░███░░███ ░░█████████  ░███████████ 
- ░███ ░░░  ░███   ███████ ░███ ░███ ░███  ░░░░░░░░███ ░███░░░░░███ 
- ░███      ░███  ███░░███ ░███ ░███ ░███  ███    ░███ ░███    ░███ 
- █████     █████░░████████░░███████ █████░░█████████  █████   █████
-░░░░░     ░░░░░  ░░░░░░░░  ░░░░░███░░░░░  ░░░░░░░░░  ░░░░░   ░░░░░ 
-                           ███ ░███                                
-                          ░░██████                                 
-                           ░░░░░░ 
+    let cli = Cli::parse();
+    if cli.command.is_none() && !cli.no_banner && !cli.quiet {
+        print_banner();
+    }
+    // The OS banner line is only interesting on the interactive prompt.
+    if !check_environment(cli.quiet || cli.command.is_some()) {
+        std::process::exit(1);
+    }
 
-v{}
-A cross-platform Rust utility to locate and run Bash scripts.
+    let mut config = Config::load();
+    let current_dir = match &cli.cwd {
+        Some(dir) if dir.is_dir() => fs::canonicalize(dir).unwrap_or_else(|_| dir.clone()),
+        Some(dir) => {
+            eprintln!("Invalid directory: {}", dir.display());
+            std::process::exit(2);
+        }
+        // Subcommands work where they were started, like any other command.
+        None if cli.command.is_some() => std::env::current_dir().unwrap(),
+        None if config.last_directory.exists() => config.last_directory.clone(),
+        None if cfg!(target_os = "windows") => {
+            dirs::desktop_dir().unwrap_or_else(|| std::env::current_dir().unwrap())
+        }
+        None => std::env::current_dir().unwrap(),
+    };
+    config.enter(&current_dir);
 
-Write help to see available commands.
-"#, env!("CARGO_PKG_VERSION"));
-    // 1) OS Detection and Preparation
-    if cfg!(target_os = "windows") {
-        println!("Windows detected.");
-        if !check_wsl2() {
-            println!("WSL2 is not installed or not enabled.");
-            open_edge_install_link();
-            return;
+    let code = match cli.command {
+        Some(command) => run_command(command, &config, &current_dir, cli.quiet),
+        None => repl(&mut config, current_dir, cli.quiet),
+    };
+    // A background refresh may still be running; let it finish its write.
+    index::ScriptIndex::close();
+    std::process::exit(code);
+}
+
+/// Runs a non-interactive subcommand and returns the process exit status.
+fn run_command(command: cli::Command, config: &Config, current_dir: &Path, quiet: bool) -> i32 {
+    match command {
+        cli::Command::Run { script, on_error, dry_run, step, parallel, args } => {
+            let mut options = RunOptions::from_config(config, quiet);
+            options.on_error = on_error.unwrap_or(options.on_error);
+            options.args = args;
+            options.dry_run = dry_run;
+            options.step = step;
+            let scripts = listing::list_scripts(current_dir, config);
+            let task_file = load_tasks(current_dir, &config.theme);
+            if let Some((file, name)) = find_task(task_file.as_ref(), &script, scripts.len()) {
+                return run_task(file, name, &options, parallel);
+            }
+            let Some(script) = resolve_script(current_dir, &scripts, &script) else {
+                return 127;
+            };
+            execute_script(&script, current_dir, &options)
         }
-    } else if cfg!(unix) {
-        println!("POSIX-compatible OS detected.");
-        if which::which("bash").is_err() {
-            eprintln!("Bash not found on this system. Unsupported OS.");
-            return;
+        cli::Command::Scan(args) => {
+            let scripts = scan::scan(&scan_options(&args, config, current_dir), args.rebuild, false, quiet);
+            if let (None, Some(format)) = (&args.output, args.format) {
+                // Without a file, a format means writing the export to stdout.
+                if let Err(e) = scan::export(&scripts, format, &mut io::stdout().lock()) {
+                    eprintln!("Failed to write script list: {}", e);
+                    return 1;
+                }
+                return 0;
+            }
+            for (i, script) in scripts.iter().enumerate() {
+                println!("  [{}] {}", i + 1, script.info.path.display());
+            }
+            if let Some(path) = &args.output {
+                let path = current_dir.join(path);
+                scan::save_list(&scripts, &path, args.format.unwrap_or_else(|| ExportFormat::for_path(&path)));
+            }
+            0
         }
-    } else {
-        eprintln!("Unsupported OS detected. Exiting.");
-        return;
+        cli::Command::List => {
+            let scripts = listing::list_scripts(current_dir, config);
+            print_script_table(current_dir, &scripts, GitStatus::load(current_dir).as_ref(), &config.theme);
+            0
+        }
+        cli::Command::Tasks => {
+            let scripts = listing::list_scripts(current_dir, config);
+            if print_tasks(current_dir, scripts.len(), &config.theme) {
+                0
+            } else {
+                1
+            }
+        }
+        cli::Command::History => {
+            CommandHistory::new(config).print(None);
+            0
+        }
+        cli::Command::Config => match serde_json::to_string_pretty(config) {
+            Ok(json) => {
+                println!("{}", json);
+                0
+            }
+            Err(e) => {
+                eprintln!("Failed to print config: {}", e);
+                1
+            }
+        },
     }
+}
 
-    let mut config = Config::load();
-    let mut current_dir = config.last_directory.clone();
-    if !current_dir.exists() {
-        current_dir = if cfg!(target_os = "windows") {
-            dirs::desktop_dir().unwrap_or_else(|| std::env::current_dir().unwrap())
-        } else {
-            std::env::current_dir().unwrap()
-        };
-    }
+/// The interactive prompt. Returns the exit status of the last script it ran, if any.
+fn repl(config: &mut Config, mut current_dir: PathBuf, quiet: bool) -> i32 {
+    let mut history = CommandHistory::new(config);
+    let mut numbers = ScriptNumbers::default();
+    let mut navigation = Navigation::default();
+    let mut exit_code = 0;
 
-    let mut history = CommandHistory::new(config.history_limit);
-    
     loop {
-        println!("\nCurrent folder: {}", current_dir.display());
-        let bash_files = list_bash_files(&current_dir);
+        let git_status = GitStatus::load(&current_dir);
+        match &git_status {
+            Some(status) => println!(
+                "\nCurrent folder: {} (git: {}{})",
+                current_dir.display(),
+                status.branch.as_deref().unwrap_or("unknown"),
+                if status.is_dirty() { ", dirty" } else { "" }
+            ),
+            None => println!("\nCurrent folder: {}", current_dir.display()),
+        }
+        let bash_files = numbers.scripts(&current_dir, config);
         if bash_files.is_empty() {
-            println!("No .sh files found in this folder.");
+            println!("No scripts found in this folder.");
         } else {
-            println!("Available Bash scripts:");
-            for (i, file) in bash_files.iter().enumerate() {
-                println!("  [{}] {}", i + 1, file.display());
-            }
+            println!("Available scripts:");
+            print_script_table(&current_dir, &bash_files, git_status.as_ref(), &config.theme);
+        }
+        let task_file = load_tasks(&current_dir, &config.theme);
+        if let Some(file) = task_file.as_ref().filter(|file| !file.tasks.is_empty()) {
+            println!("Tasks from {}:", file.path.display());
+            file.print(bash_files.len() + 1);
         }
 
-        let prompt = format!("{}> ", current_dir.display());
-        let input = match history.readline(&prompt) {
+        let prompt = prompt::render(
+            &config.prompt,
+            &PromptContext {
+                current_dir: &current_dir,
+                last_status: exit_code,
+                script_count: bash_files.len(),
+            },
+        );
+        let prompt = config.theme.paint(&config.theme.prompt, &prompt);
+        let input = match history.readline(&prompt, &current_dir, &bash_files) {
             Ok(line) => line.trim().to_string(),
             Err(_) => break,
         };
 
         if input.is_empty() {
-            if let Some(script) = auto_discover_script(&current_dir) {
-                execute_script(&script, &current_dir);
-                break;
+            if let Some((file, name)) = task_file.as_ref().and_then(|file| Some((file, file.default.as_deref()?))) {
+                if !quiet {
+                    println!("Running default task: {}", name);
+                }
+                exit_code = run_task(file, name, &RunOptions::from_config(config, quiet), false);
+            } else if let Some(script) = auto_discover_script(&bash_files) {
+                exit_code = execute_script(&script, &current_dir, &RunOptions::from_config(config, quiet));
             }
             continue;
         }
 
         match input.as_str() {
-            "ls" => list_directory(&current_dir),
+            "ls" => list_directory(&current_dir, &config.theme),
             input if input.starts_with("ls ") => {
                 let path = input[3..].trim();
                 let target_path = match path {
@@ -105,31 +225,93 @@ Write help to see available commands.
                     _ => resolve_path(&current_dir, path)
                 };
                 if target_path.exists() && target_path.is_dir() {
-                    list_directory(&target_path);
+                    list_directory(&target_path, &config.theme);
                 } else {
                     println!("Invalid path: {}", path);
                 }
             }
             "cd" => {
                 if let Some(home) = dirs::home_dir() {
-                    current_dir = home;
-                    config.last_directory = current_dir.clone();
-                    config.save().unwrap_or_else(|e| eprintln!("Failed to save config: {}", e));
+                    change_dir(config, &mut current_dir, &mut navigation, home);
                 }
             }
-            input if input.starts_with("cd ") => {
-                let path = input[3..].trim();
-                let target_path = match path {
-                    "." => current_dir.to_path_buf(),
-                    ".." => current_dir.parent().unwrap_or(&current_dir).to_path_buf(),
-                    _ => resolve_path(&current_dir, path)
+            "cd -" => match navigation.previous().filter(|dir| dir.is_dir()).map(Path::to_path_buf) {
+                Some(previous) => {
+                    println!("{}", previous.display());
+                    change_dir(config, &mut current_dir, &mut navigation, previous);
+                }
+                None => println!("cd: no previous directory"),
+            },
+            input if input.starts_with("cd ") => match target_dir(config, &current_dir, input[3..].trim()) {
+                Ok(target) => change_dir(config, &mut current_dir, &mut navigation, target),
+                Err(e) => println!("{}", e),
+            },
+            input if input.starts_with("go ") => {
+                let name = input[3..].trim();
+                match target_dir(config, &current_dir, &format!("@{}", name.trim_start_matches('@'))) {
+                    Ok(target) => change_dir(config, &mut current_dir, &mut navigation, target),
+                    Err(e) => println!("{}", e),
+                }
+            }
+            input if input == "bookmark" || input.starts_with("bookmark ") => bookmark_command(config, &input[8..], &current_dir),
+            "pushd" => match navigation.pop() {
+                Some(top) if top.is_dir() => {
+                    navigation.push(&current_dir);
+                    change_dir(config, &mut current_dir, &mut navigation, top);
+                    navigation.print(&current_dir);
+                }
+                Some(top) => println!("Invalid directory: {}", top.display()),
+                None => println!("pushd: no other directory"),
+            },
+            input if input.starts_with("pushd ") => match target_dir(config, &current_dir, input[6..].trim()) {
+                Ok(target) => {
+                    navigation.push(&current_dir);
+                    change_dir(config, &mut current_dir, &mut navigation, target);
+                    navigation.print(&current_dir);
+                }
+                Err(e) => println!("{}", e),
+            },
+            "popd" => match navigation.pop() {
+                Some(dir) if dir.is_dir() => {
+                    change_dir(config, &mut current_dir, &mut navigation, dir);
+                    navigation.print(&current_dir);
+                }
+                Some(dir) => println!("Invalid directory: {}", dir.display()),
+                None => println!("popd: directory stack empty"),
+            },
+            "dirs" => navigation.print(&current_dir),
+            input if input == "j" || input.starts_with("j ") => {
+                let args = input[1..].trim();
+                let (interactive, pattern) = match args.strip_prefix("-i") {
+                    Some(pattern) if pattern.is_empty() || pattern.starts_with(' ') => (true, pattern.trim()),
+                    _ => (false, args),
                 };
-                if target_path.exists() && target_path.is_dir() {
-                    current_dir = target_path;
-                    config.last_directory = current_dir.clone();
-                    config.save().unwrap_or_else(|e| eprintln!("Failed to save config: {}", e));
+                let database = DirectoryDatabase::load();
+                let matches = database.matches(pattern, &current_dir);
+                let target = if matches.is_empty() {
+                    println!("No visited directory matches '{}'", pattern);
+                    None
+                } else if interactive {
+                    for (i, entry) in matches.iter().take(frecency::MAX_CHOICES).enumerate() {
+                        println!("  [{}] {}", i + 1, entry.path.display());
+                    }
+                    println!("\nEnter a number to jump there, or press Enter to stay.");
+                    let mut choice = String::new();
+                    let _ = io::stdin().read_line(&mut choice);
+                    match choice.trim().parse::<usize>() {
+                        Ok(num) if num > 0 && num <= matches.len().min(frecency::MAX_CHOICES) => Some(matches[num - 1].path.clone()),
+                        Ok(_) => {
+                            println!("Invalid number.");
+                            None
+                        }
+                        Err(_) => None,
+                    }
                 } else {
-                    println!("Invalid directory: {}", path);
+                    Some(matches[0].path.clone())
+                };
+                if let Some(target) = target {
+                    println!("{}", target.display());
+                    change_dir(config, &mut current_dir, &mut navigation, target);
                 }
             }
             "help" => {
@@ -138,8 +320,55 @@ Write help to see available commands.
                 println!("  ls <path>             List files in specified directory");
                 println!("  cd                    Go to home directory");
                 println!("  cd <path>             Change to specified directory");
-                println!("  scan                  Search for all bash scripts in the system");
-                println!("  scan -o               Same as scan but also saves to magish_scripts.txt");
+                println!("  cd -                  Go back to the previous directory");
+                println!("  cd @name[/path]       Change to a bookmarked directory (or below it)");
+                println!("  go <name>             Same as cd @name");
+                println!("  bookmark add <name> [path]");
+                println!("                        Bookmark the current (or given) directory");
+                println!("  bookmark list         List bookmarks");
+                println!("  bookmark remove <name>");
+                println!("                        Delete a bookmark");
+                println!("  pushd [path]          Change directory and remember the current one;");
+                println!("                        without a path, swap with the last remembered one");
+                println!("  popd                  Go back to the last remembered directory");
+                println!("  dirs                  Show the remembered directories");
+                println!("  j <words>             Jump to the most frequently and recently visited directory");
+                println!("                        whose path contains the words, the last in its name");
+                println!("  j -i <words>          Choose among the matching directories, best first");
+                println!("  run <n|path>          Run a script; --on-error stop|continue|prompt overrides the config");
+                println!("  run <task>            Run a task from magish.toml after the tasks it depends on;");
+                println!("                        --parallel runs independent ones at the same time");
+                println!("  tasks                 List the tasks in magish.toml");
+                println!("  run --dry-run <n|path>");
+                println!("  preview <n|path>      Show what a script would run without running it");
+                println!("  step <n|path>         Step through a script, pausing before each command");
+                println!("  info <n|path>         Show a script's description, arguments, requirements and tags");
+                println!("  find <pattern>        Fuzzy-search script names and descriptions below this folder");
+                println!("  sort name|modified|usage");
+                println!("                        Change the order of the script list");
+                println!("  history               List command history");
+                println!("  history grep <text>   List history entries containing text");
+                println!("  history clear         Delete the command history");
+                println!("  !n, !!                Run history entry n, or the last entry, again");
+                println!("  runs [--failed]       List past script runs (only failed ones with --failed)");
+                println!("  rerun <id>            Run a past run's script again with the same folder and arguments");
+                println!("  logs                  List logged script runs");
+                println!("  log show <id>         Show a run's commands, exit codes and output");
+                println!("  log tail [id]         Show the end of the latest (or given) run's output");
+                println!("  [KEY=VALUE...] <n|path> [args...]");
+                println!("                        Run a script with environment overrides and arguments");
+                println!("  config [list]         Show all settings");
+                println!("  config get <key>      Show one setting, e.g. history_limit or theme.hint");
+                println!("  config set <key> <value>");
+                println!("                        Change a setting for this session and save it");
+                println!("  config reset <key>    Go back to the default (or system/project) value");
+                println!("  config edit           Edit the config file in $EDITOR and reload it");
+                println!("  scan                  Search for all scripts in the system");
+                println!("  scan -o <file> [--format text|json|csv|ndjson]");
+                println!("                        Same as scan but also saves the list to <file>");
+                println!("  scan --root <dir> --max-depth <n>");
+                println!("                        Search only below <dir>, at most <n> folders deep");
+                println!("  scan --rebuild        Scan everything again instead of starting from the index");
                 println!("  help                  Show this help message");
                 println!("  quit, exit            Exit the program");
                 println!("\nOther features:");
@@ -151,55 +380,228 @@ Write help to see available commands.
             },
             "quit" | "exit" => {
                 history.save_history().unwrap_or_else(|e| eprintln!("Failed to save history: {}", e));
-                return;
+                return exit_code;
             }
-            "scan" => {
-                let scripts = scan_filesystem(false);
+            input if input == "run" || input.starts_with("run ") => {
+                let mut options = RunOptions::from_config(config, quiet);
+                let words = parser::split_words(&input[3..]);
+                let mut rest = words.as_slice();
+                let mut valid = true;
+                let mut parallel = false;
+                while let [flag, tail @ ..] = rest {
+                    match flag.as_str() {
+                        "--dry-run" => {
+                            options.dry_run = true;
+                            rest = tail;
+                        }
+                        "--parallel" => {
+                            parallel = true;
+                            rest = tail;
+                        }
+                        "--step" => {
+                            options.step = true;
+                            rest = tail;
+                        }
+                        "--on-error" => {
+                            match tail.first().map(|value| value.parse()) {
+                                Some(Ok(policy)) => options.on_error = policy,
+                                Some(Err(e)) => {
+                                    println!("{}", e);
+                                    valid = false;
+                                }
+                                None => {
+                                    println!("--on-error needs a value: stop, continue or prompt");
+                                    valid = false;
+                                }
+                            }
+                            rest = tail.get(1..).unwrap_or_default();
+                        }
+                        _ => break,
+                    }
+                }
+                let (env, rest) = take_env_assignments(rest);
+                match rest.split_first() {
+                    Some((target, args)) if valid => {
+                        options.env = env;
+                        options.args = args.to_vec();
+                        let status = if let Some((file, name)) = find_task(task_file.as_ref(), target, bash_files.len()) {
+                            run_task(file, name, &options, parallel)
+                        } else if let Some(script) = resolve_script(&current_dir, &bash_files, target) {
+                            execute_script(&script, &current_dir, &options)
+                        } else {
+                            continue;
+                        };
+                        // A dry run changes nothing, so it keeps the last status.
+                        if !options.dry_run {
+                            exit_code = status;
+                        }
+                    }
+                    Some(_) => {}
+                    None => println!("Usage: run [--dry-run] [--step] [--parallel] [--on-error stop|continue|prompt] [KEY=VALUE...] <number|path|task> [args...]"),
+                }
+            }
+            input if input == "step" || input.starts_with("step ") => {
+                let mut options = RunOptions::from_config(config, quiet);
+                options.step = true;
+                let words = parser::split_words(&input[4..]);
+                let (env, rest) = take_env_assignments(&words);
+                match rest.split_first() {
+                    Some((target, args)) => {
+                        if let Some(script) = resolve_script(&current_dir, &bash_files, target) {
+                            options.env = env;
+                            options.args = args.to_vec();
+                            exit_code = execute_script(&script, &current_dir, &options);
+                        }
+                    }
+                    None => println!("Usage: step [KEY=VALUE...] <number|path> [args...]"),
+                }
+            }
+            "history" => history.print(None),
+            "history clear" => match history.clear() {
+                Ok(()) => println!("History cleared."),
+                Err(e) => eprintln!("Failed to clear history: {}", e),
+            },
+            input if input.starts_with("history grep ") => history.print(Some(input[13..].trim())),
+            "tasks" => {
+                print_tasks(&current_dir, bash_files.len(), &config.theme);
+            }
+            "runs" => RunDatabase::load().print(false),
+            "runs --failed" => RunDatabase::load().print(true),
+            input if input.starts_with("rerun ") => {
+                let run_db = RunDatabase::load();
+                match input[6..].trim().parse().ok().and_then(|id| run_db.get(id)) {
+                    Some(entry) => exit_code = rerun(entry, &RunOptions::from_config(config, quiet)),
+                    None => println!("No run with id {}", input[6..].trim()),
+                }
+            }
+            "logs" => logs::print_run_list(),
+            input if input.starts_with("log ") => {
+                let words: Vec<&str> = input.split_whitespace().collect();
+                match words.as_slice() {
+                    ["log", "show", id] => match id.parse() {
+                        Ok(id) => logs::print_run(id),
+                        Err(_) => println!("Invalid run id: {}", id),
+                    },
+                    ["log", "tail"] => logs::print_tail(None, 20),
+                    ["log", "tail", id] => match id.parse() {
+                        Ok(id) => logs::print_tail(Some(id), 20),
+                        Err(_) => println!("Invalid run id: {}", id),
+                    },
+                    _ => println!("Usage: log show <id> | log tail [id]"),
+                }
+            }
+            input if input.starts_with("find ") => {
+                let pattern = input[5..].trim();
+                // Searched like `scan`, so the index, excludes and .gitignore files apply.
+                let options = ScanOptions { roots: vec![current_dir.clone()], max_depth: None, exclude: config.scan_exclude.clone() };
+                let scripts = scan::scan(&options, false, true, true).into_iter().map(|script| script.info).collect();
+                let found = listing::find_scripts(&current_dir, pattern, scripts);
+                if found.is_empty() {
+                    println!("No scripts matching '{}'", pattern);
+                }
+                for script in found {
+                    let relative = script.path.strip_prefix(&current_dir).unwrap_or(&script.path).display().to_string();
+                    let row = format!(
+                        "  {}  {}",
+                        config.theme.paint(&config.theme.script, &relative),
+                        script.description.as_deref().unwrap_or("")
+                    );
+                    println!("{}", row.trim_end());
+                }
+            }
+            input if input.starts_with("sort ") => match input[5..].trim().parse::<ScriptSort>() {
+                Ok(sort) => {
+                    config.script_sort = sort;
+                    numbers.reset();
+                }
+                Err(e) => println!("{}", e),
+            },
+            input if input == "config" || input.starts_with("config ") => {
+                if config_command(config, &input[6..], &current_dir) {
+                    history.apply(config);
+                    numbers.reset();
+                }
+            }
+            "find" => println!("Usage: find <pattern>"),
+            "info" => println!("Usage: info <number|path>"),
+            "preview" => println!("Usage: preview <number|path>"),
+            input if input.starts_with("info ") => {
+                if let Some(script) = resolve_script(&current_dir, &bash_files, input[5..].trim()) {
+                    ScriptInfo::read(&script).print();
+                }
+            }
+            input if input.starts_with("preview ") => {
+                if let Some(script) = resolve_script(&current_dir, &bash_files, input[8..].trim()) {
+                    preview_script(&script, &current_dir, &RunOptions::from_config(config, quiet));
+                }
+            }
+            input if input == "scan" || input.starts_with("scan ") => {
+                let args = match cli::ScanArgs::parse_words(&parser::split_words(&input[4..])) {
+                    Ok(args) => args,
+                    Err(e) => {
+                        println!("{}", e.render());
+                        continue;
+                    }
+                };
+                let scripts = scan::scan(&scan_options(&args, config, &current_dir), args.rebuild, true, quiet);
+                if let Some(path) = &args.output {
+                    let path = current_dir.join(path);
+                    scan::save_list(&scripts, &path, args.format.unwrap_or_else(|| ExportFormat::for_path(&path)));
+                }
                 for (i, script) in scripts.iter().enumerate() {
-                    println!("  [{}] {}", i + 1, script.display());
+                    println!("  [{}] {}", i + 1, script.info.path.display());
                 }
                 println!("\nEnter a number to run a script, or press Enter to continue.");
                 let mut choice = String::new();
                 if io::stdin().read_line(&mut choice).is_ok() {
                     if let Ok(num) = choice.trim().parse::<usize>() {
                         if num > 0 && num <= scripts.len() {
-                            execute_script(&scripts[num - 1], &current_dir);
-                            break;
+                            exit_code = execute_script(&scripts[num - 1].info.path, &current_dir, &RunOptions::from_config(config, quiet));
                         } else {
                             println!("Invalid script number.");
                         }
                     }
                 }
             }
-            input if input.starts_with("scan -o") => {
-                let scripts = scan_filesystem(true);
-                println!("Scan complete. Found {} scripts.", scripts.len());
-            }
             input => {
+                let mut options = RunOptions::from_config(config, quiet);
+                // A bare path may contain spaces, so try the whole line first.
+                let target = if resolve_path(&current_dir, input).exists() {
+                    input.to_string()
+                } else {
+                    let words = parser::split_words(input);
+                    let (env, words) = take_env_assignments(&words);
+                    let Some((target, args)) = words.split_first() else {
+                        println!("Missing script after environment overrides");
+                        continue;
+                    };
+                    options.env = env;
+                    options.args = args.to_vec();
+                    target.clone()
+                };
+
                 // Try to parse as a number first
-                if let Ok(num) = input.parse::<usize>() {
-                    let bash_files = list_bash_files(&current_dir);
+                if let Ok(num) = target.parse::<usize>() {
+                    let task_count = task_file.as_ref().map_or(0, |file| file.tasks.len());
                     if num > 0 && num <= bash_files.len() {
-                        execute_script(&bash_files[num - 1], &current_dir);
-                        break;
+                        exit_code = execute_script(&bash_files[num - 1], &current_dir, &options);
+                    } else if let Some((file, name)) = find_task(task_file.as_ref(), &target, bash_files.len()) {
+                        exit_code = run_task(file, name, &options, false);
                     } else {
-                        println!("Invalid script number. Please choose between 1 and {}", if bash_files.is_empty() { 1 } else { bash_files.len() });
+                        println!("Invalid script number. Please choose between 1 and {}", (bash_files.len() + task_count).max(1));
                     }
                 } else {
-                    let target_path = resolve_path(&current_dir, input);
+                    let target_path = resolve_path(&current_dir, &target);
                     if target_path.exists() {
-                        if target_path.is_dir() {
-                            current_dir = target_path;
-                            config.last_directory = current_dir.clone();
-                            config.save().unwrap_or_else(|e| eprintln!("Failed to save config: {}", e));
-                        } else if target_path.extension().and_then(|s| s.to_str()) == Some("sh") {
-                            execute_script(&target_path, &current_dir);
-                            break;
+                        if target_path.is_dir() && options.args.is_empty() && options.env.is_empty() {
+                            change_dir(config, &mut current_dir, &mut navigation, target_path);
+                        } else if detect::detect(&target_path).is_some() {
+                            exit_code = execute_script(&target_path, &current_dir, &options);
                         } else {
-                            println!("Not a directory or shell script: {}", input);
+                            println!("Not a shell script: {}", target);
                         }
                     } else {
-                        println!("Path does not exist: {}", input);
+                        println!("Path does not exist: {}", target);
                     }
                 }
             }
@@ -207,8 +609,142 @@ Write help to see available commands.
     }
 
     history.save_history().unwrap_or_else(|e| eprintln!("Failed to save history: {}", e));
-    println!("Press Enter to exit...");
-    let _ = io::stdin().read_line(&mut String::new());
+    // Keeps a console window opened by double-clicking around; pointless when piped.
+    if io::stdin().is_terminal() {
+        println!("Press Enter to exit...");
+        let _ = io::stdin().read_line(&mut String::new());
+    }
+    exit_code
+}
+
+fn print_banner() {
+    println!(r#"
+ ██████   ██████                     ███   █████████  █████   █████
+░░██████ ██████                     ░░░   ███░░░░░███░░███   ░░███ 
+ ░███░█████░███   ██████    ███████ ████ ░███    ░░░  ░███    ░███ 
+ ░███░░███ ░███  ░░░░░███  ███░░███░░███ ░░█████████  ░███████████ 
+ ░███ ░░░  ░███   ███████ ░███ ░███ ░███  ░░░░░░░░███ ░███░░░░░███ 
+ ░███      ░███  ███░░███ ░███ ░███ ░███  ███    ░███ ░███    ░███ 
+ █████     █████░░████████░░███████ █████░░█████████  █████   █████
+░░░░░     ░░░░░  ░░░░░░░░  ░░░░░███░░░░░  ░░░░░░░░░  ░░░░░   ░░░░░ 
+                           ███ ░███                                
+                          ░░██████                                 
+                           ░░░░░░ 
+
+v{}
+A cross-platform Rust utility to locate and run Bash scripts.
+
+Write help to see available commands.
+"#, env!("CARGO_PKG_VERSION"));
+}
+
+/// Checks that Bash (or WSL2 on Windows) is available.
+fn check_environment(quiet: bool) -> bool {
+    if cfg!(target_os = "windows") {
+        if !quiet {
+            println!("Windows detected.");
+        }
+        if !check_wsl2() {
+            println!("WSL2 is not installed or not enabled.");
+            open_edge_install_link();
+            return false;
+        }
+    } else if cfg!(unix) {
+        if !quiet {
+            println!("POSIX-compatible OS detected.");
+        }
+        if which::which("bash").is_err() {
+            eprintln!("Bash not found on this system. Unsupported OS.");
+            return false;
+        }
+    } else {
+        eprintln!("Unsupported OS detected. Exiting.");
+        return false;
+    }
+
+    true
+}
+
+
+/// Moves the prompt to `target`, remembering the folder it left for `cd -`,
+/// the visit for `j`, and `target` as the folder to start from next time.
+fn change_dir(config: &mut Config, current_dir: &mut PathBuf, navigation: &mut Navigation, target: PathBuf) {
+    if target != *current_dir {
+        navigation.left(current_dir);
+    }
+    *current_dir = target;
+    DirectoryDatabase::record(current_dir);
+    config.last_directory = current_dir.clone();
+    config.enter(current_dir);
+    config.save().unwrap_or_else(|e| eprintln!("Failed to save config: {}", e));
+}
+
+/// The folder `path` names for `cd`, `pushd` and `go`: `.`, `..`, a path
+/// relative to `current_dir` or absolute, or `@bookmark` optionally
+/// followed by `/subfolder`.
+fn target_dir(config: &Config, current_dir: &Path, path: &str) -> Result<PathBuf, String> {
+    let target = match path {
+        "." => current_dir.to_path_buf(),
+        ".." => current_dir.parent().unwrap_or(current_dir).to_path_buf(),
+        _ => match path.strip_prefix('@') {
+            Some(bookmark) => {
+                let (name, rest) = bookmark.split_once('/').unwrap_or((bookmark, ""));
+                let dir = config.bookmarks.get(name).ok_or_else(|| format!("Unknown bookmark '{}'", name))?;
+                if rest.is_empty() { dir.clone() } else { dir.join(rest) }
+            }
+            None => resolve_path(current_dir, path),
+        },
+    };
+    if target.is_dir() {
+        Ok(target)
+    } else {
+        Err(format!("Invalid directory: {}", path))
+    }
+}
+
+/// Runs `bookmark add|list|remove` at the prompt.
+fn bookmark_command(config: &mut Config, args: &str, current_dir: &Path) {
+    let words = parser::split_words(args);
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let result = match words.as_slice() {
+        [] | ["list"] => {
+            if config.bookmarks.is_empty() {
+                println!("No bookmarks; add one with `bookmark add <name>`");
+            }
+            let width = config.bookmarks.keys().map(String::len).max().unwrap_or(0);
+            for (name, dir) in &config.bookmarks {
+                println!("  @{:<width$}  {}", name, dir.display(), width = width);
+            }
+            return;
+        }
+        ["add", name] | ["add", name, _] => {
+            let name = name.trim_start_matches('@');
+            let dir = match words.get(2) {
+                Some(path) => target_dir(config, current_dir, path),
+                None => Ok(current_dir.to_path_buf()),
+            };
+            if name.is_empty() || name.contains('/') {
+                Err(format!("Invalid bookmark name '{}'", name))
+            } else {
+                dir.map(|dir| {
+                    println!("@{} -> {}", name, dir.display());
+                    config.bookmarks.insert(name.to_string(), dir);
+                })
+            }
+        }
+        ["remove", name] => {
+            let name = name.trim_start_matches('@');
+            match config.bookmarks.remove(name) {
+                Some(_) => Ok(()),
+                None => Err(format!("Unknown bookmark '{}'", name)),
+            }
+        }
+        _ => Err("Usage: bookmark [list] | add <name> [path] | remove <name>".to_string()),
+    };
+    match result {
+        Ok(()) => config.save().unwrap_or_else(|e| eprintln!("Failed to save config: {}", e)),
+        Err(e) => println!("{}", e),
+    }
 }
 
 fn resolve_path(current_dir: &Path, path: &str) -> PathBuf {
@@ -219,15 +755,15 @@ fn resolve_path(current_dir: &Path, path: &str) -> PathBuf {
     }
 }
 
-fn list_directory(dir: &Path) {
+fn list_directory(dir: &Path, theme: &Theme) {
     if let Ok(entries) = fs::read_dir(dir) {
         for entry in entries.flatten() {
             let path = entry.path();
             let file_name = path.file_name().unwrap_or_default().to_string_lossy();
             if path.is_dir() {
-                println!("\x1b[1;34m{}/\x1b[0m", file_name);
-            } else if path.extension().and_then(|s| s.to_str()) == Some("sh") {
-                println!("\x1b[1;32m{}\x1b[0m", file_name);
+                println!("{}", theme.paint(&theme.directory, &format!("{}/", file_name)));
+            } else if detect::detect(&path).is_some() {
+                println!("{}", theme.paint(&theme.script, &file_name));
             } else {
                 println!("{}", file_name);
             }
@@ -237,77 +773,573 @@ fn list_directory(dir: &Path) {
     }
 }
 
-fn execute_script(script_path: &Path, current_dir: &Path) {
-    println!("Using script: {}", script_path.display());
-    if let Ok(contents) = fs::read_to_string(script_path) {
-        let mut working_dir = current_dir.to_path_buf();
-        for line in contents.lines() {
-            let cmd = line.trim();
-            if cmd.is_empty() || cmd.starts_with('#') {
-                continue;
-            }
-            println!("Executing: {}", cmd);
-            
-            // If this is a cd command, update the working directory
-            if cmd.starts_with("cd ") {
-                let dir = cmd[3..].trim();
-                let new_dir = if dir.starts_with('/') {
-                    PathBuf::from(dir)
-                } else {
-                    working_dir.join(dir)
-                };
-                if new_dir.exists() && new_dir.is_dir() {
-                    working_dir = new_dir;
-                }
-            }
-            
-            let mut command = if cfg!(target_os = "windows") {
-                let mut c = Command::new("wsl");
-                c.arg("bash").arg("-c").arg(cmd);
-                c
-            } else {
-                let mut c = Command::new("bash");
-                c.arg("-c").arg(cmd);
-                c
-            };
-            command.current_dir(&working_dir);
-            let mut child = command
-                .stdout(Stdio::inherit())
-                .stderr(Stdio::inherit())
-                .spawn()
-                .expect("Failed to spawn shell process");
-            let _ = child.wait();
-            thread::sleep(Duration::from_millis(100));
+/// Per-run settings for `execute_script`.
+#[derive(Clone)]
+struct RunOptions {
+    mode: ExecutionMode,
+    on_error: FailurePolicy,
+    /// Positional parameters (`$1..$n`) for the script.
+    args: Vec<String>,
+    /// Extra environment variables for the script.
+    env: Vec<(String, String)>,
+    /// Copy the script's output into its run log.
+    log_output: bool,
+    /// Hide magish's own trace and only show the script's output.
+    quiet: bool,
+    /// Only show what would run; see `preview_script`.
+    dry_run: bool,
+    /// Pause before each command; see `Stepper`.
+    step: bool,
+    /// Interpreter for scripts that don't name one; see `executor::for_script`.
+    executor: Interpreter,
+    theme: Theme,
+    /// The `magish.toml` and name of the task being run, for `rerun`.
+    task: Option<(PathBuf, String)>,
+}
+
+impl RunOptions {
+    fn from_config(config: &Config, quiet: bool) -> Self {
+        Self {
+            mode: config.execution_mode,
+            on_error: config.on_error,
+            args: Vec::new(),
+            env: Vec::new(),
+            log_output: config.log_output,
+            quiet,
+            dry_run: false,
+            step: false,
+            executor: config.executor,
+            theme: config.theme.clone(),
+            task: None,
+        }
+    }
+}
+
+/// Runs a script and returns its exit status: 0 on success, otherwise the
+/// status of the command that failed.
+fn execute_script(script_path: &Path, current_dir: &Path, options: &RunOptions) -> i32 {
+    let Ok(contents) = fs::read_to_string(script_path) else {
+        eprintln!("Failed to read script file.");
+        return 1;
+    };
+    let executor = executor::for_script(&ScriptInfo::read(script_path), options.executor);
+    execute_source(script_path, &contents, executor.as_ref(), current_dir, options)
+}
+
+/// Runs `contents` with `executor`. `script_path` names the source in the
+/// trace and the run log; for a task's inline commands it is not a file.
+fn execute_source(script_path: &Path, contents: &str, executor: &dyn Executor, current_dir: &Path, options: &RunOptions) -> i32 {
+    if options.dry_run {
+        return preview_source(script_path, contents, executor, current_dir, options);
+    }
+    if !options.quiet {
+        println!("Using script: {}", script_path.display());
+    }
+    let statements = parser::parse_statements(contents);
+    let content_hash = runs::content_hash(contents);
+    let mut run_db = RunDatabase::load();
+    if let Some(previous) = run_db.last_success(script_path).filter(|_| !options.quiet) {
+        if previous.content_hash == content_hash {
+            println!("Unchanged since its last successful run (#{}).", previous.id);
+        } else {
+            let note = format!(
+                "Script has changed since its last successful run (#{}, {} UTC).",
+                previous.id,
+                logs::format_timestamp(previous.started_at)
+            );
+            println!("{}", options.theme.paint(&options.theme.warning, &note));
         }
+    }
+    let mut log = RunLog::create(script_path, options.log_output);
+    let status = match options.mode {
+        _ if !executor.runs_by_command() => run_whole_script(script_path, executor, current_dir, options, &mut log),
+        ExecutionMode::Session => run_in_session(&statements, executor, current_dir, options, &mut log),
+        ExecutionMode::PerLine => run_per_line(&statements, script_path, executor, current_dir, options, &mut log),
+    };
+    if !options.quiet {
+        println!("Run logged as #{} (see `log show {}`)", log.id(), log.id());
+    }
+    let record = log.finish(status);
+    run_db.add(&record, content_hash, current_dir, &options.args, &options.env, options.task.as_ref());
+    if options.quiet {
+        // Nothing to add; the exit status speaks for itself.
+    } else if status == 0 {
         println!("All commands executed.");
     } else {
+        println!("Script failed with status {}", status);
+    }
+    status
+}
+
+/// Runs all commands in one shell process so shell state persists between them.
+fn run_in_session(statements: &[Statement], executor: &dyn Executor, current_dir: &Path, options: &RunOptions, log: &mut RunLog) -> i32 {
+    let session = executor
+        .session_command(&options.args)
+        .ok_or_else(|| io::Error::other("sessions are not supported"))
+        .and_then(|command| ShellSession::start(command, current_dir, &options.env, log.stdout(), log.stderr()));
+    let mut session = match session {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Failed to start {} session: {}", executor.name(), e);
+            return 127;
+        }
+    };
+    let result = run_commands(statements, current_dir, options, log, |cmd, working_dir| match session.run(cmd) {
+        Ok(Some(status)) => {
+            *working_dir = status.working_dir;
+            Some(status.code)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("Lost connection to bash session: {}", e);
+            None
+        }
+    });
+    match (result, session.finish()) {
+        (Some(status), _) => status,
+        // The shell exited on its own (`exit`, `set -e`), so its status is the script's.
+        (None, Ok(code)) => {
+            if !options.quiet {
+                println!("Shell exited with status {}", code);
+            }
+            code
+        }
+        (None, Err(e)) => {
+            eprintln!("Failed to close bash session: {}", e);
+            1
+        }
+    }
+}
+
+/// Runs every command in its own `bash -c`, tracking `cd` between them.
+fn run_per_line(
+    statements: &[Statement],
+    script_path: &Path,
+    executor: &dyn Executor,
+    current_dir: &Path,
+    options: &RunOptions,
+    log: &mut RunLog,
+) -> i32 {
+    let (output, errors) = (log.stdout(), log.stderr());
+    let result = run_commands(statements, current_dir, options, log, |cmd, working_dir| {
+        track_cd(cmd, working_dir);
+
+        let Some(mut command) = executor.line_command(cmd, script_path, &options.args) else {
+            eprintln!("{} can't run single commands", executor.name());
+            return Some(127);
+        };
+        session::set_env(&mut command, &options.env);
+        command.current_dir(&*working_dir);
+        let status = command
+            .stdout(output.stdio())
+            .stderr(errors.stdio())
+            .spawn()
+            .and_then(|mut child| {
+                let copies = [
+                    child.stdout.take().map(|stdout| output.spawn_copy(stdout)),
+                    child.stderr.take().map(|stderr| errors.spawn_copy(stderr)),
+                ];
+                let status = child.wait();
+                copies.into_iter().flatten().for_each(|copy| {
+                    let _ = copy.join();
+                });
+                status
+            });
+        thread::sleep(Duration::from_millis(100));
+        match status {
+            Ok(status) => Some(status.code().unwrap_or(-1)),
+            Err(e) => {
+                eprintln!("Failed to spawn shell process: {}", e);
+                Some(127)
+            }
+        }
+    });
+    result.unwrap_or(0)
+}
+
+/// Hands the whole script to an executor that can't run it command by command.
+fn run_whole_script(script_path: &Path, executor: &dyn Executor, current_dir: &Path, options: &RunOptions, log: &mut RunLog) -> i32 {
+    if options.step {
+        println!("{} scripts can't be stepped through; running the whole script.", executor.name());
+    }
+    let mut command = executor.script_command(script_path, &options.args);
+    command.current_dir(current_dir);
+    session::set_env(&mut command, &options.env);
+    let description = format!("{} {}", executor.name(), script_path.display());
+    if !options.quiet {
+        println!("Executing: {}", description);
+    }
+    let started = Instant::now();
+    let (output, errors) = (log.stdout(), log.stderr());
+    let status = command
+        .stdout(output.stdio())
+        .stderr(errors.stdio())
+        .spawn()
+        .and_then(|mut child| {
+            let copies = [
+                child.stdout.take().map(|stdout| output.spawn_copy(stdout)),
+                child.stderr.take().map(|stderr| errors.spawn_copy(stderr)),
+            ];
+            let status = child.wait();
+            copies.into_iter().flatten().for_each(|copy| {
+                let _ = copy.join();
+            });
+            status
+        });
+    let code = match status {
+        Ok(status) => status.code().unwrap_or(-1),
+        Err(e) => {
+            eprintln!("Failed to start {}: {}", executor.name(), e);
+            127
+        }
+    };
+    log.record_command(1, &description, code, started.elapsed().as_millis() as u64);
+    if code != 0 {
+        log.set_failed_line(1);
+    }
+    if !options.quiet {
+        println!("Exit status: {}", code);
+    }
+    code
+}
+
+/// If `cmd` is a `cd` command, updates the working directory to follow it.
+fn track_cd(cmd: &str, working_dir: &mut PathBuf) {
+    if let Some(dir) = cmd.strip_prefix("cd ") {
+        let dir = dir.trim();
+        let new_dir = if dir.starts_with('/') {
+            PathBuf::from(dir)
+        } else {
+            working_dir.join(dir)
+        };
+        if new_dir.exists() && new_dir.is_dir() {
+            *working_dir = new_dir;
+        }
+    }
+}
+
+/// Shows which commands `execute_script` would dispatch and in which folder,
+/// and which lines it would skip, without running anything.
+fn preview_script(script_path: &Path, current_dir: &Path, options: &RunOptions) -> i32 {
+    let Ok(contents) = fs::read_to_string(script_path) else {
         eprintln!("Failed to read script file.");
+        return 1;
+    };
+    let executor = executor::for_script(&ScriptInfo::read(script_path), options.executor);
+    preview_source(script_path, &contents, executor.as_ref(), current_dir, options)
+}
+
+fn preview_source(script_path: &Path, contents: &str, executor: &dyn Executor, current_dir: &Path, options: &RunOptions) -> i32 {
+    println!("Preview of {} (nothing will be run)", script_path.display());
+    if !options.args.is_empty() {
+        println!("Arguments: {}", options.args.join(" "));
+    }
+    for (key, value) in &options.env {
+        println!("Environment: {}={}", key, value);
+    }
+    if !executor.runs_by_command() {
+        println!("Would run: {} {} in {}", executor.name(), script_path.display(), current_dir.display());
+        return 0;
     }
+
+    let lines: Vec<&str> = contents.lines().collect();
+    let mut working_dir = current_dir.to_path_buf();
+    let mut next_line = 1;
+    for statement in parser::parse_statements(contents) {
+        let skipped_lines = lines.get(next_line - 1..statement.line.saturating_sub(1)).unwrap_or_default();
+        for (offset, skipped) in skipped_lines.iter().enumerate() {
+            if !skipped.trim().is_empty() {
+                println!("{:>5}  skip  {}", next_line + offset, skipped.trim());
+            }
+        }
+        track_cd(&statement.text, &mut working_dir);
+        println!("{:>5}  run   [{}] {}", statement.line, working_dir.display(), statement.text.replace('\n', "\n             "));
+        next_line = next_line.max(statement.end_line() + 1);
+    }
+    for (offset, skipped) in lines.iter().enumerate().skip(next_line - 1) {
+        if !skipped.trim().is_empty() {
+            println!("{:>5}  skip  {}", offset + 1, skipped.trim());
+        }
+    }
+    0
 }
 
-fn list_bash_files(dir: &Path) -> Vec<PathBuf> {
-    let mut files = Vec::new();
-    if let Ok(entries) = fs::read_dir(dir) {
-        for e in entries.flatten() {
-            let path = e.path();
-            if path.extension().and_then(|s| s.to_str()) == Some("sh") && path.is_file() {
-                files.push(path);
+/// Dispatches each statement through `run` and applies the failure policy,
+/// pausing before each one when stepping.
+///
+/// `run` returns the command's exit status, or `None` if the shell went away,
+/// and updates the working directory it is given. Returns `None` if the shell
+/// went away, otherwise the status of the last failure that was not skipped
+/// (0 if there was none).
+fn run_commands(
+    statements: &[Statement],
+    current_dir: &Path,
+    options: &RunOptions,
+    log: &mut RunLog,
+    mut run: impl FnMut(&str, &mut PathBuf) -> Option<i32>,
+) -> Option<i32> {
+    let mut stepper = options.step.then(Stepper::new);
+    let mut working_dir = current_dir.to_path_buf();
+    let mut failed = 0;
+    for statement in statements {
+        let cmd = match stepper.as_mut().map(|stepper| stepper.pause(statement, &working_dir)) {
+            None => statement.text.clone(),
+            Some(StepAction::Run(cmd)) => cmd,
+            Some(StepAction::Skip) => continue,
+            Some(StepAction::Abort) => return Some(failed),
+        };
+        loop {
+            if !options.quiet {
+                println!("Executing: {}", cmd);
+            }
+            let previous_dir = working_dir.clone();
+            let started = Instant::now();
+            let Some(code) = run(&cmd, &mut working_dir) else {
+                // The shell exited during this command; the run only failed if
+                // its final status says so.
+                log.set_failed_line(statement.line);
+                return None;
+            };
+            log.record_command(statement.line, &cmd, code, started.elapsed().as_millis() as u64);
+            if !options.quiet {
+                println!("Exit status: {}", code);
+                if working_dir != previous_dir {
+                    println!("Working directory: {}", working_dir.display());
+                }
+            }
+            if code == 0 {
+                break;
+            }
+            log.set_failed_line(statement.line);
+            match options.on_error {
+                FailurePolicy::Continue => {
+                    failed = code;
+                    break;
+                }
+                FailurePolicy::Stop => {
+                    if !options.quiet {
+                        println!("Stopping at failed command.");
+                    }
+                    return Some(code);
+                }
+                FailurePolicy::Prompt => match prompt_on_failure(code) {
+                    FailureChoice::Retry => continue,
+                    FailureChoice::Skip => break,
+                    FailureChoice::Abort => return Some(code),
+                },
             }
         }
     }
-    files
+    Some(failed)
 }
 
-fn auto_discover_script(dir: &Path) -> Option<PathBuf> {
-    let names = ["base.sh", "index.sh", "script.sh"];
-    for name in &names {
-        let pb = dir.join(name);
-        if pb.exists() && pb.is_file() {
-            println!("Auto-detected script: {}", pb.display());
-            return Some(pb);
+/// Runs `config list|get|set|reset|edit` at the prompt. Returns whether the
+/// settings changed.
+fn config_command(config: &mut Config, args: &str, current_dir: &Path) -> bool {
+    // The value is the rest of the line, so it can contain spaces or JSON.
+    let mut parts = args.trim().splitn(3, char::is_whitespace);
+    let (action, key, value) = (parts.next().unwrap_or(""), parts.next(), parts.next().map(str::trim));
+    let result = match (action, key, value) {
+        ("" | "list", None, None) => {
+            for (key, value) in config.list() {
+                println!("{} = {}", key, value);
+            }
+            return false;
+        }
+        ("get", Some(key), None) => config.get(key).map(|value| {
+            println!("{}", serde_json::to_string_pretty(&value).unwrap_or_default());
+        }),
+        ("set", Some(key), Some(value)) => config.set(key, value).map(|_| print_setting(config, key)),
+        ("reset", Some(key), None) => config.reset(key).map(|_| print_setting(config, key)),
+        ("edit", None, None) => return edit_config(config, current_dir),
+        _ => Err("Usage: config [list] | get <key> | set <key> <value> | reset <key> | edit".to_string()),
+    };
+    match result {
+        Ok(()) if action != "get" => {
+            config.save().unwrap_or_else(|e| eprintln!("Failed to save config: {}", e));
+            true
+        }
+        Ok(()) => false,
+        Err(e) => {
+            println!("{}", e);
+            false
         }
     }
-    let bash_files = list_bash_files(dir);
+}
+
+fn print_setting(config: &Config, key: &str) {
+    if let Ok(value) = config.get(key) {
+        println!("{} = {}", key, value);
+    }
+}
+
+/// Opens the user's config file in `$VISUAL` or `$EDITOR` and loads it
+/// again, offering another try while it isn't valid JSON. Returns whether
+/// the settings were reloaded.
+fn edit_config(config: &mut Config, current_dir: &Path) -> bool {
+    // The file should include what was changed in this session.
+    if let Err(e) = config.save() {
+        eprintln!("Failed to save config: {}", e);
+        return false;
+    }
+    let path = Config::get_config_path();
+    let default_editor = if cfg!(target_os = "windows") { "notepad" } else { "vi" };
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| default_editor.to_string());
+    let words = parser::split_words(&editor);
+    let Some((program, editor_args)) = words.split_first() else {
+        println!("No editor set; set $EDITOR");
+        return false;
+    };
+    loop {
+        match Command::new(program).args(editor_args).arg(&path).status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                println!("{} exited with {}; settings not reloaded", program, status);
+                return false;
+            }
+            Err(e) => {
+                println!("Failed to start {}: {}", program, e);
+                return false;
+            }
+        }
+        match config.reload(current_dir) {
+            Ok(()) => {
+                println!("Reloaded {}", path.display());
+                return true;
+            }
+            Err(e) => {
+                print!("{}: {}\nEdit again? [Y/n] ", path.display(), e);
+                let _ = io::stdout().flush();
+                let mut answer = String::new();
+                if io::stdin().read_line(&mut answer).unwrap_or(0) == 0 || answer.trim().eq_ignore_ascii_case("n") {
+                    println!("Keeping the previous settings.");
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+enum FailureChoice {
+    Retry,
+    Skip,
+    Abort,
+}
+
+fn prompt_on_failure(code: i32) -> FailureChoice {
+    loop {
+        print!("Command failed with status {}. [r]etry, [s]kip or [a]bort? ", code);
+        let _ = io::stdout().flush();
+        let mut choice = String::new();
+        if io::stdin().read_line(&mut choice).unwrap_or(0) == 0 {
+            return FailureChoice::Abort;
+        }
+        match choice.trim() {
+            "r" | "retry" => return FailureChoice::Retry,
+            "s" | "skip" => return FailureChoice::Skip,
+            "a" | "abort" => return FailureChoice::Abort,
+            _ => {}
+        }
+    }
+}
+
+/// Splits leading `KEY=VALUE` words off a command line.
+fn take_env_assignments(words: &[String]) -> (Vec<(String, String)>, &[String]) {
+    let mut env = Vec::new();
+    for (i, word) in words.iter().enumerate() {
+        match word.split_once('=') {
+            Some((key, value)) if is_env_name(key) => env.push((key.to_string(), value.to_string())),
+            _ => return (env, &words[i..]),
+        }
+    }
+    (env, &[])
+}
+
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Resolves a script given by list number or path, printing why if it can't.
+fn resolve_script(current_dir: &Path, bash_files: &[PathBuf], target: &str) -> Option<PathBuf> {
+    if let Ok(num) = target.parse::<usize>() {
+        if num > 0 && num <= bash_files.len() {
+            return Some(bash_files[num - 1].clone());
+        }
+        println!("Invalid script number. Please choose between 1 and {}", bash_files.len().max(1));
+        return None;
+    }
+    let path = resolve_path(current_dir, target);
+    if path.is_file() {
+        Some(path)
+    } else {
+        println!("Script not found: {}", target);
+        None
+    }
+}
+
+/// Prints the numbered script picker: one row per script with its
+/// interpreter, size, modification time, executable bit, description and git
+/// state.
+fn print_script_table(dir: &Path, files: &[PathBuf], git_status: Option<&GitStatus>, theme: &Theme) {
+    let scripts: Vec<ScriptInfo> = files.iter().map(|file| ScriptInfo::read(file)).collect();
+    let names: Vec<String> = files.iter().map(|file| file.strip_prefix(dir).unwrap_or(file).display().to_string()).collect();
+    let name_width = names.iter().map(|name| name.chars().count()).max().unwrap_or(0).max(6);
+    let number_width = scripts.len().to_string().len() + 2;
+    println!(
+        "  {:>nw$}  {:<w$}  {:<6}  {:>5}  {:<19}  {:<4}  Description",
+        "#",
+        "Script",
+        "Type",
+        "Size",
+        "Modified (UTC)",
+        "Exec",
+        nw = number_width,
+        w = name_width
+    );
+    for (i, script) in scripts.iter().enumerate() {
+        let mark = match git_status.map(|status| status.file_state(&script.path)) {
+            Some(FileState::Modified) => "  [modified]",
+            Some(FileState::Untracked) => "  [untracked]",
+            _ => "",
+        };
+        let row = format!(
+            "  {:>nw$}  {}  {:<6}  {:>5}  {:<19}  {:<4}  {}{}",
+            format!("[{}]", i + 1),
+            theme.paint(&theme.script, &format!("{:<w$}", names[i], w = name_width)),
+            script.interpreter.map_or("-".to_string(), |interpreter| interpreter.to_string()),
+            metadata::format_size(script.size),
+            script.modified.map_or("-".to_string(), logs::format_timestamp),
+            if script.executable { "x" } else { "-" },
+            script.description.as_deref().unwrap_or(""),
+            theme.paint(&theme.warning, mark),
+            nw = number_width
+        );
+        println!("{}", row.trim_end());
+    }
+}
+
+/// Combines `scan` options with the configured exclude globs. Roots are
+/// relative to `current_dir` and canonicalized, so that one folder always
+/// has the same entries in the index.
+fn scan_options(args: &cli::ScanArgs, config: &Config, current_dir: &Path) -> ScanOptions {
+    let roots = args.roots.iter().map(|root| {
+        let root = current_dir.join(root);
+        fs::canonicalize(&root).unwrap_or(root)
+    });
+    ScanOptions {
+        roots: if args.roots.is_empty() { ScanOptions::default_roots() } else { roots.collect() },
+        max_depth: args.max_depth,
+        exclude: config.scan_exclude.clone(),
+    }
+}
+
+/// The script Enter runs on an empty prompt when magish.toml names no
+/// default task: the first one listed.
+fn auto_discover_script(bash_files: &[PathBuf]) -> Option<PathBuf> {
     if !bash_files.is_empty() {
         println!("Auto-detected script: {}", bash_files[0].display());
         return Some(bash_files[0].clone());
@@ -315,6 +1347,109 @@ fn auto_discover_script(dir: &Path) -> Option<PathBuf> {
     None
 }
 
+/// The nearest magish.toml, with a warning if it can't be used.
+fn load_tasks(dir: &Path, theme: &Theme) -> Option<TaskFile> {
+    TaskFile::find(dir).unwrap_or_else(|e| {
+        eprintln!("{}", theme.paint(&theme.warning, &e));
+        None
+    })
+}
+
+/// Prints the tasks of the nearest magish.toml, numbered after the
+/// `script_count` scripts. Returns whether there was a usable file.
+fn print_tasks(dir: &Path, script_count: usize, theme: &Theme) -> bool {
+    match TaskFile::find(dir) {
+        Ok(Some(file)) if file.tasks.is_empty() => println!("{} defines no tasks.", file.path.display()),
+        Ok(Some(file)) => file.print(script_count + 1),
+        Ok(None) => println!("No magish.toml in this folder or its parents."),
+        Err(e) => {
+            eprintln!("{}", theme.paint(&theme.warning, &e));
+            return false;
+        }
+    }
+    true
+}
+
+/// The task `target` names, by name or by its number in the picker, where
+/// tasks follow the `script_count` scripts.
+fn find_task<'a>(file: Option<&'a TaskFile>, target: &str, script_count: usize) -> Option<(&'a TaskFile, &'a str)> {
+    let file = file?;
+    let name = match target.parse::<usize>() {
+        Ok(num) if num > script_count => *file.names().get(num - script_count - 1)?,
+        Ok(_) => return None,
+        Err(_) => file.tasks.get_key_value(target)?.0.as_str(),
+    };
+    Some((file, name))
+}
+
+/// Runs task `name` after the tasks it depends on. `options.args` are added
+/// to the arguments of `name` itself; `options.env` applies to every task.
+fn run_task(file: &TaskFile, name: &str, options: &RunOptions, parallel: bool) -> i32 {
+    let plan = match file.plan(name) {
+        Ok(plan) => plan,
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+    // Tasks running side by side can't share the terminal's input.
+    let parallel = parallel && !options.step && options.on_error != FailurePolicy::Prompt;
+    let status = file.run_plan(&plan, parallel, |task_name, task| {
+        let mut task_options = options.clone();
+        task_options.args = task.args.clone();
+        if task_name == name {
+            task_options.args.extend(options.args.iter().cloned());
+        }
+        task_options.env = task.env.iter().map(|(key, value)| (key.clone(), value.clone())).collect();
+        task_options.env.extend(options.env.iter().cloned());
+        task_options.task = Some((file.path.clone(), task_name.to_string()));
+        if !options.quiet {
+            println!("==> Task {}", task_name);
+        }
+        let working_dir = file.working_dir(task);
+        let source = file.source(task_name, task);
+        match &task.run {
+            Some(commands) => {
+                let executor = executor::for_commands(options.executor);
+                execute_source(&source, commands, executor.as_ref(), &working_dir, &task_options)
+            }
+            None => execute_script(&source, &working_dir, &task_options),
+        }
+    });
+    if status != 0 && !options.quiet {
+        println!("Task {} failed with status {}", name, status);
+    }
+    status
+}
+
+/// Runs a past run again with the same folder, arguments and environment.
+/// Runs of a task go through `run_task`, with the arguments and environment
+/// the task itself adds left for it to add again.
+fn rerun(entry: &RunEntry, options: &RunOptions) -> i32 {
+    let mut options = options.clone();
+    options.args = entry.args.clone();
+    options.env = entry.env.clone();
+    let (Some(path), Some(name)) = (&entry.task_file, &entry.task) else {
+        return execute_script(&entry.script, &entry.cwd, &options);
+    };
+    let file = match TaskFile::read(path) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+    let Some(task) = file.tasks.get(name) else {
+        println!("Task {} is no longer in {}", name, path.display());
+        return 1;
+    };
+    if let Some(extra) = entry.args.strip_prefix(task.args.as_slice()) {
+        options.args = extra.to_vec();
+    }
+    options.env.retain(|(key, value)| task.env.get(key) != Some(value));
+    run_task(&file, name, &options, false)
+}
+
 /// Checks if WSL2 is installed and available.
 fn check_wsl2() -> bool {
     if let Ok(output) = Command::new("wsl").arg("-l").arg("-v").output() {
@@ -329,95 +1464,9 @@ fn check_wsl2() -> bool {
 fn open_edge_install_link() {
     let url = "https://docs.microsoft.com/windows/wsl/install";
     let _ = Command::new("cmd")
-        .args(&["/C", "start", "ms-edge:" , url])
+        .args(["/C", "start", "ms-edge:" , url])
         .spawn();
     println!("Opening Edge to guide for WSL2 installation...");
 }
 
-/// Recursively scans the filesystem for bash scripts.
-/// If save_to_file is true, saves the list to "magish_scripts.txt".
-fn scan_filesystem(save_to_file: bool) -> Vec<PathBuf> {
-    use std::collections::HashSet;
-    use std::io::Write;
-    let mut scripts = HashSet::new();
-    
-    // Start from common directories
-    let start_dirs = vec![
-        dirs::home_dir(),
-        Some(PathBuf::from("/")),
-        dirs::document_dir(),
-        dirs::desktop_dir(),
-    ];
-
-    println!("Scanning filesystem for bash scripts...");
-    let start_time = std::time::Instant::now();
-    let mut last_update = std::time::Instant::now();
-    let mut files_scanned = 0;
-    let mut scripts_found = 0;
-    let mut loading_chars = ['⣾', '⣽', '⣻', '⢿', '⡿', '⣟', '⣯', '⣷'].iter().cycle();
-
-    // Start scanning
-    for start_dir in start_dirs.into_iter().flatten() {
-        // Define a closure for updating the loading bar
-        let mut update_progress = |current_scripts: usize| {
-            files_scanned += 1;
-            scripts_found = current_scripts;
-            if last_update.elapsed() >= std::time::Duration::from_millis(100) {
-                print!("\r{} Files scanned: {}, Scripts found: {}, Time: {:?}    ", 
-                    loading_chars.next().unwrap(),
-                    files_scanned,
-                    scripts_found,
-                    start_time.elapsed());
-                let _ = std::io::stdout().flush();
-                last_update = std::time::Instant::now();
-            }
-        };
-        
-        scan_dir_recursively(&start_dir, &mut scripts, &mut update_progress);
-    }
-
-    println!("\nScan complete! Found {} scripts in {:?}", scripts.len(), start_time.elapsed());
-    
-    let mut scripts: Vec<_> = scripts.into_iter().collect();
-    scripts.sort();
-
-    if save_to_file {
-        if let Ok(mut file) = fs::File::create("magish_scripts.txt") {
-            use std::io::Write;
-            for (i, script) in scripts.iter().enumerate() {
-                if let Err(e) = writeln!(file, "[{}] {}", i + 1, script.display()) {
-                    eprintln!("Error writing to file: {}", e);
-                    break;
-                }
-            }
-            println!("Script list saved to magish_scripts.txt");
-        }
-    }
-
-    scripts
-}
-
-/// Helper function for scanning directories recursively
-fn scan_dir_recursively(dir: &Path, scripts: &mut HashSet<PathBuf>, update_progress: &mut dyn FnMut(usize)) {
-    if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            
-            if path.is_dir() {
-                // Skip hidden directories and certain system paths
-                if let Some(name) = path.file_name().and_then(|s| s.to_str()) {
-                    if !name.starts_with('.') && 
-                       !name.contains("node_modules") && 
-                       !name.contains("target") &&
-                       !name.contains("vendor") {
-                        scan_dir_recursively(&path, scripts, update_progress);
-                    }
-                }
-            } else if path.extension().and_then(|s| s.to_str()) == Some("sh") {
-                scripts.insert(path.clone());
-            }
-            update_progress(scripts.len());
-        }
-    }
-}
 
@@ -0,0 +1,169 @@
+use crate::detect::{self, Interpreter};
+use crate::logs::format_timestamp;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// How many lines at the top of a script are searched for header tags.
+const HEADER_LINES: usize = 100;
+
+/// What a script says about itself in its header, plus file details.
+///
+/// The header is the leading block of comment lines:
+///
+/// ```sh
+/// #!/usr/bin/env bash
+/// # @description Deploy the site to staging.
+/// #   Lines without a tag continue the previous tag.
+/// # @args <environment> [--force]
+/// # @requires rsync, ssh
+/// # @tags deploy, web
+/// # @executor zsh
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ScriptInfo {
+    pub path: PathBuf,
+    /// The interpreter line without `#!`.
+    pub shebang: Option<String>,
+    pub interpreter: Option<Interpreter>,
+    /// Interpreter named by an `@executor` tag, overriding the shebang.
+    pub executor: Option<Interpreter>,
+    pub description: Option<String>,
+    pub args: Option<String>,
+    pub requires: Vec<String>,
+    pub tags: Vec<String>,
+    pub size: u64,
+    /// Unix seconds of the last modification.
+    pub modified: Option<u64>,
+    pub executable: bool,
+}
+
+impl ScriptInfo {
+    pub fn read(path: &Path) -> Self {
+        let mut info = Self {
+            path: path.to_path_buf(),
+            interpreter: detect::detect(path),
+            ..Self::default()
+        };
+        if let Ok(metadata) = fs::metadata(path) {
+            info.size = metadata.len();
+            info.modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs());
+            info.executable = is_executable(&metadata);
+        }
+        let Ok(file) = File::open(path) else {
+            return info;
+        };
+
+        let mut current: Option<(String, String)> = None;
+        for (i, line) in BufReader::new(file).lines().map_while(Result::ok).take(HEADER_LINES).enumerate() {
+            let line = line.trim();
+            if i == 0 {
+                if let Some(shebang) = line.strip_prefix("#!") {
+                    info.shebang = Some(shebang.trim().to_string());
+                    continue;
+                }
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let Some(comment) = line.strip_prefix('#') else {
+                break;
+            };
+            let comment = comment.trim();
+            if let Some(tagged) = comment.strip_prefix('@') {
+                info.apply(current.take());
+                let (tag, value) = tagged.split_once(char::is_whitespace).unwrap_or((tagged, ""));
+                current = Some((tag.to_lowercase(), value.trim().to_string()));
+            } else if let Some((_, value)) = current.as_mut() {
+                if comment.is_empty() {
+                    info.apply(current.take());
+                } else {
+                    if !value.is_empty() {
+                        value.push(' ');
+                    }
+                    value.push_str(comment);
+                }
+            }
+        }
+        info.apply(current);
+        info.interpreter = info.executor.or(info.interpreter);
+        info
+    }
+
+    fn apply(&mut self, tag: Option<(String, String)>) {
+        let Some((tag, value)) = tag else {
+            return;
+        };
+        let list = || value.split([',', ' ']).filter(|item| !item.is_empty()).map(str::to_string).collect();
+        match tag.as_str() {
+            "description" => self.description = Some(value.clone()),
+            "args" => self.args = Some(value.clone()),
+            "requires" => self.requires = list(),
+            "tags" => self.tags = list(),
+            "executor" => match value.parse() {
+                Ok(executor) => self.executor = Some(executor),
+                Err(e) => eprintln!("{}: {}", self.path.display(), e),
+            },
+            _ => {}
+        }
+    }
+
+    /// Prints everything known about the script for the `info` command.
+    pub fn print(&self) {
+        println!("Script:      {}", self.path.display());
+        match (self.interpreter, &self.shebang) {
+            (Some(interpreter), Some(shebang)) => println!("Interpreter: {} (#!{})", interpreter, shebang),
+            (Some(interpreter), None) => println!("Interpreter: {} (no shebang)", interpreter),
+            (None, Some(shebang)) => println!("Interpreter: unknown (#!{})", shebang),
+            (None, None) => println!("Interpreter: unknown (no shebang)"),
+        }
+        println!("Description: {}", self.description.as_deref().unwrap_or("-"));
+        println!("Arguments:   {}", self.args.as_deref().unwrap_or("-"));
+        println!("Requires:    {}", join_or_dash(&self.requires));
+        println!("Tags:        {}", join_or_dash(&self.tags));
+        println!("Size:        {} ({} bytes)", format_size(self.size), self.size);
+        println!("Modified:    {}", self.modified.map_or("-".to_string(), |secs| format!("{} UTC", format_timestamp(secs))));
+        println!("Executable:  {}", if self.executable { "yes" } else { "no" });
+    }
+}
+
+/// Formats a byte count as e.g. `512B`, `1.5K` or `12M`.
+pub fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "K", "M", "G"] {
+        if size < 1024.0 || unit == "G" {
+            return if unit == "B" || size >= 10.0 {
+                format!("{:.0}{}", size, unit)
+            } else {
+                format!("{:.1}{}", size, unit)
+            };
+        }
+        size /= 1024.0;
+    }
+    unreachable!()
+}
+
+fn join_or_dash(items: &[String]) -> String {
+    if items.is_empty() {
+        "-".to_string()
+    } else {
+        items.join(", ")
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    false
+}
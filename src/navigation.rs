@@ -0,0 +1,40 @@
+//! Folder history of the prompt session, for `cd -`, `pushd`, `popd` and
+//! `dirs`.
+
+use std::path::{Path, PathBuf};
+
+#[derive(Default)]
+pub struct Navigation {
+    /// The folder before the last move, where `cd -` goes.
+    previous: Option<PathBuf>,
+    /// The `pushd` stack, top last.
+    stack: Vec<PathBuf>,
+}
+
+impl Navigation {
+    /// Records that the prompt moved away from `dir`.
+    pub fn left(&mut self, dir: &Path) {
+        self.previous = Some(dir.to_path_buf());
+    }
+
+    pub fn previous(&self) -> Option<&Path> {
+        self.previous.as_deref()
+    }
+
+    pub fn push(&mut self, dir: &Path) {
+        self.stack.push(dir.to_path_buf());
+    }
+
+    pub fn pop(&mut self) -> Option<PathBuf> {
+        self.stack.pop()
+    }
+
+    /// Prints the stack like `dirs -v`: the current folder as 0, then the
+    /// folders `popd` would return to, in order.
+    pub fn print(&self, current_dir: &Path) {
+        println!("{:>3}  {}", 0, current_dir.display());
+        for (i, dir) in self.stack.iter().rev().enumerate() {
+            println!("{:>3}  {}", i + 1, dir.display());
+        }
+    }
+}
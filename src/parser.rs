@@ -0,0 +1,606 @@
+//! Groups script source lines into complete shell statements.
+//!
+//! This is not a full shell parser. It tracks just enough state (quotes,
+//! substitutions, compound commands, heredocs and line continuations) to know
+//! where one statement ends, so each statement can be dispatched on its own.
+//! It also splits what the user types at the prompt into quoted words.
+
+/// A complete statement and the 1-based line it starts on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub line: usize,
+    pub text: String,
+}
+
+impl Statement {
+    /// The 1-based line the statement ends on.
+    pub fn end_line(&self) -> usize {
+        self.line + self.text.lines().count().max(1) - 1
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    DoubleQuote,
+    Backtick,
+    /// `${...}`
+    Param,
+    /// `(...)`, `$(...)` or one half of `((...))`
+    Paren { arith: bool },
+    If,
+    Case,
+    /// `for`/`while`/`until`/`select` up to `done`
+    Loop,
+    Brace,
+}
+
+struct Heredoc {
+    delimiter: String,
+    strip_tabs: bool,
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    stack: Vec<Context>,
+    statements: Vec<Statement>,
+    text: String,
+    start_line: usize,
+    word: String,
+    word_quoted: bool,
+    command_position: bool,
+    /// Set after `name()` or `function name` until the body starts.
+    expect_body: bool,
+    /// The last token on the line was `|`, `&&` or `||`.
+    pipe_pending: bool,
+    heredocs: Vec<Heredoc>,
+    /// Position in `text` just after each open `(`, to recognise `name()`.
+    paren_starts: Vec<usize>,
+}
+
+/// Splits `source` into statements, dropping blank lines and comments.
+pub fn parse_statements(source: &str) -> Vec<Statement> {
+    let mut parser = Parser {
+        chars: source.chars().collect(),
+        pos: 0,
+        line: 1,
+        stack: Vec::new(),
+        statements: Vec::new(),
+        text: String::new(),
+        start_line: 1,
+        word: String::new(),
+        word_quoted: false,
+        command_position: true,
+        expect_body: false,
+        pipe_pending: false,
+        heredocs: Vec::new(),
+        paren_starts: Vec::new(),
+    };
+    parser.run();
+    parser.statements
+}
+
+/// Splits a line typed at the prompt into words, honouring quotes.
+///
+/// Outside quotes a backslash only escapes whitespace, quotes and itself, so
+/// Windows paths like `C:\scripts\a.sh` survive unchanged.
+pub fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') if chars.peek().is_some_and(|n| matches!(n, '"' | '\\' | '$' | '`')) => {
+                word.extend(chars.next());
+            }
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, '\\') if chars.peek().is_some_and(|n| n.is_whitespace() || matches!(n, '\'' | '"' | '\\')) => {
+                word.extend(chars.next());
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+impl Parser {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn push(&mut self, c: char) {
+        if self.text.trim().is_empty() && !c.is_whitespace() {
+            self.text.clear();
+            self.start_line = self.line;
+        }
+        self.text.push(c);
+        if c == '\n' {
+            self.line += 1;
+        }
+    }
+
+    /// Copies the next `n` characters into the statement text.
+    fn take(&mut self, n: usize) {
+        for _ in 0..n {
+            if let Some(c) = self.peek(0) {
+                self.push(c);
+                self.pos += 1;
+            }
+        }
+    }
+
+    fn run(&mut self) {
+        while let Some(c) = self.peek(0) {
+            match self.stack.last() {
+                Some(Context::DoubleQuote) => self.double_quoted(c),
+                Some(Context::Backtick) => self.backticked(c),
+                Some(Context::Param) => self.param(c),
+                _ => self.code(c),
+            }
+        }
+        self.end_word();
+        self.emit();
+    }
+
+    fn double_quoted(&mut self, c: char) {
+        match c {
+            '\\' => self.take(2),
+            '"' => {
+                self.stack.pop();
+                self.take(1);
+            }
+            '`' => {
+                self.stack.push(Context::Backtick);
+                self.take(1);
+            }
+            '$' => self.dollar(),
+            _ => self.take(1),
+        }
+    }
+
+    fn backticked(&mut self, c: char) {
+        match c {
+            '\\' => self.take(2),
+            '`' => {
+                self.stack.pop();
+                self.take(1);
+            }
+            _ => self.take(1),
+        }
+    }
+
+    fn param(&mut self, c: char) {
+        match c {
+            '\\' => self.take(2),
+            '}' => {
+                self.stack.pop();
+                self.take(1);
+            }
+            '\'' => self.single_quoted(),
+            '"' => {
+                self.stack.push(Context::DoubleQuote);
+                self.take(1);
+            }
+            '$' => self.dollar(),
+            _ => self.take(1),
+        }
+    }
+
+    /// Handles `$(`, `$((`, `${` and `$'`; anything else is copied as-is.
+    fn dollar(&mut self) {
+        match self.peek(1) {
+            Some('(') => {
+                let arith = self.peek(2) == Some('(');
+                self.take(1);
+                self.open_paren(arith);
+                if arith {
+                    self.open_paren(arith);
+                }
+            }
+            Some('{') => {
+                self.stack.push(Context::Param);
+                self.take(2);
+            }
+            Some('\'') if !matches!(self.stack.last(), Some(Context::DoubleQuote)) => {
+                self.take(2);
+                while let Some(c) = self.peek(0) {
+                    match c {
+                        '\\' => self.take(2),
+                        '\'' => {
+                            self.take(1);
+                            break;
+                        }
+                        _ => self.take(1),
+                    }
+                }
+            }
+            _ => self.take(1),
+        }
+    }
+
+    fn single_quoted(&mut self) {
+        self.take(1);
+        while let Some(c) = self.peek(0) {
+            self.take(1);
+            if c == '\'' {
+                break;
+            }
+        }
+    }
+
+    fn code(&mut self, c: char) {
+        match c {
+            '\n' => {
+                self.end_word();
+                self.take(1);
+                self.read_heredocs();
+                self.command_position = true;
+                if self.stack.is_empty() && !self.pipe_pending && !self.expect_body {
+                    self.emit();
+                }
+            }
+            ' ' | '\t' | '\r' => {
+                self.end_word();
+                self.take(1);
+            }
+            '#' if self.word.is_empty() => {
+                while self.peek(0).is_some_and(|c| c != '\n') {
+                    self.pos += 1;
+                }
+            }
+            '\\' => {
+                self.word.push(c);
+                self.word_quoted = true;
+                self.take(2);
+            }
+            '\'' => {
+                self.word_quoted = true;
+                self.word.push(c);
+                self.single_quoted();
+            }
+            '"' => {
+                self.word_quoted = true;
+                self.word.push(c);
+                self.stack.push(Context::DoubleQuote);
+                self.take(1);
+            }
+            '`' => {
+                self.word_quoted = true;
+                self.word.push(c);
+                self.stack.push(Context::Backtick);
+                self.take(1);
+            }
+            '$' => {
+                self.word.push(c);
+                self.pipe_pending = false;
+                self.dollar();
+            }
+            '(' => {
+                self.end_word();
+                let arith = self.command_position && self.peek(1) == Some('(');
+                self.open_paren(arith);
+                if arith {
+                    self.open_paren(arith);
+                }
+                self.expect_body = false;
+                self.pipe_pending = false;
+                self.command_position = true;
+            }
+            ')' => {
+                self.end_word();
+                match self.stack.last() {
+                    Some(Context::Paren { .. }) => {
+                        self.stack.pop();
+                        if let Some(start) = self.paren_starts.pop() {
+                            if self.text[start..].trim().is_empty() {
+                                self.expect_body = true;
+                            }
+                        }
+                    }
+                    // A `case` pattern such as `start)`
+                    Some(Context::Case) => self.command_position = true,
+                    _ => {}
+                }
+                self.take(1);
+            }
+            ';' | '&' | '|' => {
+                self.end_word();
+                let doubled = self.peek(1) == Some(c);
+                self.pipe_pending = c == '|' || (c == '&' && doubled);
+                self.command_position = true;
+                self.take(if doubled { 2 } else { 1 });
+            }
+            '<' if self.peek(1) == Some('<') && self.peek(2) != Some('<') && !self.in_arithmetic() => {
+                self.end_word();
+                self.take(2);
+                self.heredoc_operator();
+            }
+            '<' | '>' => {
+                self.end_word();
+                self.take(1);
+            }
+            _ => {
+                self.word.push(c);
+                self.pipe_pending = false;
+                self.take(1);
+            }
+        }
+    }
+
+    fn open_paren(&mut self, arith: bool) {
+        self.stack.push(Context::Paren { arith });
+        self.take(1);
+        self.paren_starts.push(self.text.len());
+    }
+
+    fn in_arithmetic(&self) -> bool {
+        self.stack.iter().any(|ctx| matches!(ctx, Context::Paren { arith: true }))
+    }
+
+    /// Reads the delimiter after `<<` or `<<-`; the body is read at the next newline.
+    fn heredoc_operator(&mut self) {
+        let strip_tabs = self.peek(0) == Some('-');
+        if strip_tabs {
+            self.take(1);
+        }
+        while matches!(self.peek(0), Some(' ' | '\t')) {
+            self.take(1);
+        }
+        let mut delimiter = String::new();
+        let mut quote: Option<char> = None;
+        while let Some(c) = self.peek(0) {
+            match (quote, c) {
+                (None, '\'' | '"') => quote = Some(c),
+                (Some(q), c) if c == q => quote = None,
+                (None, '\\') => {}
+                (None, c) if c.is_whitespace() || ";&|<>()".contains(c) => break,
+                (_, c) => delimiter.push(c),
+            }
+            self.take(1);
+        }
+        self.heredocs.push(Heredoc { delimiter, strip_tabs });
+    }
+
+    /// Copies pending heredoc bodies, including their terminator lines.
+    fn read_heredocs(&mut self) {
+        for heredoc in std::mem::take(&mut self.heredocs) {
+            while self.peek(0).is_some() {
+                let mut body_line = String::new();
+                while let Some(c) = self.peek(0) {
+                    self.take(1);
+                    if c == '\n' {
+                        break;
+                    }
+                    body_line.push(c);
+                }
+                let body_line = if heredoc.strip_tabs { body_line.trim_start_matches('\t') } else { &body_line };
+                if body_line.trim_end_matches('\r') == heredoc.delimiter {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn end_word(&mut self) {
+        if self.word.is_empty() {
+            return;
+        }
+        let word = std::mem::take(&mut self.word);
+        let quoted = std::mem::replace(&mut self.word_quoted, false);
+        self.pipe_pending = false;
+        if !self.command_position || quoted {
+            return;
+        }
+        self.command_position = false;
+        match word.as_str() {
+            "if" => self.open(Context::If),
+            "case" => self.open(Context::Case),
+            "while" | "until" => self.open(Context::Loop),
+            "for" | "select" => {
+                self.open(Context::Loop);
+                self.command_position = false;
+            }
+            "{" => {
+                self.open(Context::Brace);
+                self.expect_body = false;
+            }
+            "fi" => self.close(Context::If),
+            "esac" => self.close(Context::Case),
+            "done" => self.close(Context::Loop),
+            "}" => self.close(Context::Brace),
+            "then" | "else" | "elif" | "do" | "!" | "time" => self.command_position = true,
+            "function" => self.expect_body = true,
+            _ => {}
+        }
+    }
+
+    fn open(&mut self, ctx: Context) {
+        self.stack.push(ctx);
+        self.command_position = ctx != Context::Case;
+    }
+
+    fn close(&mut self, ctx: Context) {
+        if self.stack.last() == Some(&ctx) {
+            self.stack.pop();
+        }
+    }
+
+    fn emit(&mut self) {
+        let text = std::mem::take(&mut self.text);
+        let text = text.trim();
+        if !text.is_empty() {
+            self.statements.push(Statement {
+                line: self.start_line,
+                text: text.to_string(),
+            });
+        }
+        self.stack.clear();
+        self.paren_starts.clear();
+        self.pipe_pending = false;
+        self.expect_body = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(source: &str) -> Vec<String> {
+        parse_statements(source).into_iter().map(|s| s.text).collect()
+    }
+
+    #[test]
+    fn split_words_honours_quotes() {
+        assert_eq!(
+            split_words(r#"FOO="a b" deploy.sh 'x y' "" plain\ word C:\dir\s.sh"#),
+            vec!["FOO=a b", "deploy.sh", "x y", "", "plain word", r"C:\dir\s.sh"]
+        );
+    }
+
+    #[test]
+    fn simple_lines_and_comments() {
+        let stmts = parse_statements("# header\necho one\n\n  echo two # trailing\n");
+        assert_eq!(
+            stmts,
+            vec![
+                Statement { line: 2, text: "echo one".into() },
+                Statement { line: 4, text: "echo two".into() },
+            ]
+        );
+    }
+
+    #[test]
+    fn hash_inside_words_is_not_a_comment() {
+        assert_eq!(texts("echo a#b $# '#x'\n"), vec!["echo a#b $# '#x'"]);
+    }
+
+    #[test]
+    fn backslash_continuation() {
+        assert_eq!(
+            texts("docker run \\\n  --rm \\\n  image\necho done\n"),
+            vec!["docker run \\\n  --rm \\\n  image", "echo done"]
+        );
+    }
+
+    #[test]
+    fn quotes_spanning_lines() {
+        assert_eq!(
+            texts("echo \"first\nsecond\"\necho 'a\nb'\necho next\n"),
+            vec!["echo \"first\nsecond\"", "echo 'a\nb'", "echo next"]
+        );
+    }
+
+    #[test]
+    fn heredoc_body_is_kept_with_its_command() {
+        let src = "cat <<EOF > out.txt\nif this looks like code\n# or a comment\nEOF\necho after\n";
+        assert_eq!(
+            texts(src),
+            vec!["cat <<EOF > out.txt\nif this looks like code\n# or a comment\nEOF", "echo after"]
+        );
+    }
+
+    #[test]
+    fn quoted_and_tab_stripped_heredocs() {
+        let src = "cat <<-'END'\n\t$HOME\n\tEND\ncat <<< \"here string\"\n";
+        assert_eq!(texts(src), vec!["cat <<-'END'\n\t$HOME\n\tEND", "cat <<< \"here string\""]);
+    }
+
+    #[test]
+    fn arithmetic_shift_is_not_a_heredoc() {
+        assert_eq!(texts("x=$(( 1 << 4 ))\necho $x\n"), vec!["x=$(( 1 << 4 ))", "echo $x"]);
+    }
+
+    #[test]
+    fn if_blocks_nest() {
+        let src = "if [ -f a ]; then\n  if true\n  then\n    echo yes\n  fi\nelse\n  echo no\nfi\necho end\n";
+        let stmts = parse_statements(src);
+        assert_eq!(stmts.len(), 2);
+        assert!(stmts[0].text.ends_with("fi"));
+        assert_eq!(stmts[1], Statement { line: 9, text: "echo end".into() });
+    }
+
+    #[test]
+    fn loops() {
+        let src = "for f in *.txt\ndo\n  echo $f\ndone\nwhile read -r l; do echo $l; done < file\necho x\n";
+        assert_eq!(
+            texts(src),
+            vec!["for f in *.txt\ndo\n  echo $f\ndone", "while read -r l; do echo $l; done < file", "echo x"]
+        );
+    }
+
+    #[test]
+    fn case_patterns_do_not_close_parens() {
+        let src = "case \"$1\" in\n  start) echo go ;;\n  (stop|halt) echo stop ;;\n  *) echo \"$(date)\" ;;\nesac\necho after\n";
+        let stmts = parse_statements(src);
+        assert_eq!(stmts.len(), 2);
+        assert!(stmts[0].text.ends_with("esac"));
+        assert_eq!(stmts[1].text, "echo after");
+    }
+
+    #[test]
+    fn function_bodies() {
+        let src = "greet() {\n  echo hi\n}\nfunction bye\n{\n  echo bye\n}\nsay ()\n{ echo say; }\ngreet\n";
+        assert_eq!(
+            texts(src),
+            vec![
+                "greet() {\n  echo hi\n}",
+                "function bye\n{\n  echo bye\n}",
+                "say ()\n{ echo say; }",
+                "greet",
+            ]
+        );
+    }
+
+    #[test]
+    fn brace_groups_and_subshells() {
+        let src = "{\n  echo a\n  echo b\n} > log\n(\n  cd /tmp\n  ls\n)\necho ${HOME}\n";
+        assert_eq!(
+            texts(src),
+            vec!["{\n  echo a\n  echo b\n} > log", "(\n  cd /tmp\n  ls\n)", "echo ${HOME}"]
+        );
+    }
+
+    #[test]
+    fn trailing_pipes_and_logical_operators_continue() {
+        assert_eq!(
+            texts("make build &&\n  make test ||\n  echo failed\ncat x |\n  grep y\n"),
+            vec!["make build &&\n  make test ||\n  echo failed", "cat x |\n  grep y"]
+        );
+    }
+
+    #[test]
+    fn command_substitution_across_lines() {
+        assert_eq!(texts("v=$(\n  echo \"(\"\n)\necho $v\n"), vec!["v=$(\n  echo \"(\"\n)", "echo $v"]);
+    }
+
+    #[test]
+    fn keywords_only_count_in_command_position() {
+        assert_eq!(texts("echo if then fi\necho done\n"), vec!["echo if then fi", "echo done"]);
+    }
+
+    #[test]
+    fn unterminated_input_is_emitted_at_eof() {
+        assert_eq!(texts("if true; then\n  echo x\n"), vec!["if true; then\n  echo x"]);
+    }
+}
@@ -0,0 +1,41 @@
+use crate::git;
+use std::path::Path;
+
+/// What the prompt template's placeholders are filled in from.
+pub struct PromptContext<'a> {
+    pub current_dir: &'a Path,
+    pub last_status: i32,
+    pub script_count: usize,
+}
+
+/// Fills in a prompt template. Supported placeholders:
+///
+/// - `{cwd}`: the current folder
+/// - `{cwd_short}`: the current folder with the home folder shown as `~`
+/// - `{branch}`: the git branch, or nothing outside a repository
+/// - `{status}`: the exit status of the last script run
+/// - `{scripts}`: the number of scripts in the current folder
+/// - `{time}`: the local time as `HH:MM:SS`
+pub fn render(template: &str, ctx: &PromptContext) -> String {
+    let mut prompt = template.replace("{cwd}", &ctx.current_dir.display().to_string());
+    if prompt.contains("{cwd_short}") {
+        prompt = prompt.replace("{cwd_short}", &shorten_home(ctx.current_dir));
+    }
+    if prompt.contains("{branch}") {
+        prompt = prompt.replace("{branch}", &git::current_branch(ctx.current_dir).unwrap_or_default());
+    }
+    if prompt.contains("{time}") {
+        prompt = prompt.replace("{time}", &chrono::Local::now().format("%H:%M:%S").to_string());
+    }
+    prompt
+        .replace("{status}", &ctx.last_status.to_string())
+        .replace("{scripts}", &ctx.script_count.to_string())
+}
+
+fn shorten_home(dir: &Path) -> String {
+    match dirs::home_dir().and_then(|home| dir.strip_prefix(home).ok().map(Path::to_path_buf)) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => dir.display().to_string(),
+    }
+}
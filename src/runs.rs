@@ -0,0 +1,144 @@
+use crate::config::Config;
+use crate::logs::{format_timestamp, RunRecord};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// How many runs `runs.json` keeps before dropping the oldest.
+const MAX_RUNS: usize = 1000;
+
+/// Held while `runs.json` is updated, since tasks can run in parallel.
+static SAVING: Mutex<()> = Mutex::new(());
+
+/// One script execution. The id is shared with the run's log.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunEntry {
+    pub id: u64,
+    pub script: PathBuf,
+    pub content_hash: String,
+    pub cwd: PathBuf,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub exit_status: i32,
+    pub failed_line: Option<usize>,
+    /// The `magish.toml` of the task this run was part of, if any.
+    #[serde(default)]
+    pub task_file: Option<PathBuf>,
+    /// Name of that task.
+    #[serde(default)]
+    pub task: Option<String>,
+}
+
+/// Structured history of script executions, stored in `runs.json`.
+pub struct RunDatabase {
+    entries: Vec<RunEntry>,
+}
+
+impl RunDatabase {
+    pub fn load() -> Self {
+        let entries = fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self { entries }
+    }
+
+    /// Records a finished run from its log plus what the log doesn't know.
+    /// `task` is the task file and name for runs of a task.
+    pub fn add(&mut self, record: &RunRecord, content_hash: String, cwd: &Path, args: &[String], env: &[(String, String)], task: Option<&(PathBuf, String)>) {
+        let _saving = SAVING.lock().unwrap_or_else(|e| e.into_inner());
+        // Keep runs that finished since this was loaded.
+        self.entries = Self::load().entries;
+        self.entries.push(RunEntry {
+            id: record.id,
+            script: record.script.clone(),
+            content_hash,
+            cwd: cwd.to_path_buf(),
+            args: args.to_vec(),
+            env: env.to_vec(),
+            started_at: record.started_at,
+            finished_at: record.finished_at.unwrap_or(record.started_at),
+            exit_status: record.exit_status.unwrap_or(-1),
+            failed_line: record.failed_line,
+            task_file: task.map(|(file, _)| file.clone()),
+            task: task.map(|(_, name)| name.clone()),
+        });
+        if self.entries.len() > MAX_RUNS {
+            self.entries.drain(..self.entries.len() - MAX_RUNS);
+        }
+        if let Err(e) = self.save() {
+            eprintln!("Failed to save run history: {}", e);
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<&RunEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
+    pub fn last_success(&self, script: &Path) -> Option<&RunEntry> {
+        self.entries.iter().rev().find(|entry| entry.script == script && entry.exit_status == 0)
+    }
+
+    /// How many times each script has been run.
+    pub fn run_counts(&self) -> HashMap<PathBuf, usize> {
+        let mut counts = HashMap::new();
+        for entry in &self.entries {
+            *counts.entry(entry.script.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Prints the run history for `runs` / `runs --failed`.
+    pub fn print(&self, failed_only: bool) {
+        let entries: Vec<&RunEntry> = self.entries.iter().filter(|entry| !failed_only || entry.exit_status != 0).collect();
+        if entries.is_empty() {
+            println!("{}", if failed_only { "No failed runs." } else { "No runs recorded yet." });
+            return;
+        }
+        println!("{:>5}  {:<19}  {:>6}  {:>6}  {:<8}  Script", "Id", "Started (UTC)", "Status", "Line", "Hash");
+        for entry in entries {
+            let line = entry.failed_line.map_or("-".to_string(), |line| line.to_string());
+            let mut script = entry.script.display().to_string();
+            if !entry.args.is_empty() {
+                script = format!("{} {}", script, entry.args.join(" "));
+            }
+            println!(
+                "{:>5}  {}  {:>6}  {:>6}  {:<8}  {}",
+                entry.id,
+                format_timestamp(entry.started_at),
+                entry.exit_status,
+                line,
+                &entry.content_hash[..8.min(entry.content_hash.len())],
+                script
+            );
+        }
+    }
+
+    /// Writes `runs.json` to a temporary file and renames it into place, so
+    /// a crash mid-write doesn't lose the history.
+    fn save(&self) -> std::io::Result<()> {
+        fs::create_dir_all(Config::state_dir())?;
+        let json = serde_json::to_string_pretty(&self.entries)?;
+        let temp = Self::path().with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&temp, json)?;
+        fs::rename(&temp, Self::path()).inspect_err(|_| {
+            let _ = fs::remove_file(&temp);
+        })
+    }
+
+    fn path() -> PathBuf {
+        Config::state_dir().join("runs.json")
+    }
+}
+
+/// Stable 64-bit FNV-1a hash of a script's contents, as hex.
+pub fn content_hash(contents: &str) -> String {
+    let hash = contents.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
@@ -0,0 +1,509 @@
+//! Filesystem-wide script search for the `scan` command.
+
+use crate::detect;
+use crate::index::{IndexedFolder, IndexedScript, ScriptIndex};
+use crate::logs::format_timestamp;
+use glob::{MatchOptions, Pattern};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+/// Set while a background refresh of the index is running.
+static REFRESHING: AtomicBool = AtomicBool::new(false);
+
+/// Virtual filesystems with nothing worth finding and, in `/proc`, endless
+/// self-references.
+const PSEUDO_FILESYSTEMS: &[&str] = &["/proc", "/sys", "/dev"];
+
+/// What to scan and what to leave out.
+#[derive(Clone)]
+pub struct ScanOptions {
+    pub roots: Vec<PathBuf>,
+    /// How many folder levels below each root to descend; unlimited if `None`.
+    pub max_depth: Option<usize>,
+    /// Globs in `.gitignore` syntax for folders and files to skip everywhere.
+    pub exclude: Vec<String>,
+}
+
+impl ScanOptions {
+    /// The folders a plain `scan` starts from.
+    pub fn default_roots() -> Vec<PathBuf> {
+        [dirs::home_dir(), Some(PathBuf::from("/")), dirs::document_dir(), dirs::desktop_dir()]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
+
+/// One line of a `.gitignore` file (or a configured exclude glob).
+pub struct IgnoreRule {
+    /// Folder the rule was found in; anchored patterns are relative to it.
+    base: PathBuf,
+    pattern: Pattern,
+    negated: bool,
+    dir_only: bool,
+    /// Patterns containing a `/` match the path below `base`, others match
+    /// the name at any depth.
+    anchored: bool,
+}
+
+impl IgnoreRule {
+    fn parse(base: &Path, line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        Some(Self {
+            base: base.to_path_buf(),
+            pattern: Pattern::new(line).ok()?,
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &Path, name: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let options = MatchOptions { require_literal_separator: true, ..MatchOptions::default() };
+        if self.anchored {
+            path.strip_prefix(&self.base).is_ok_and(|relative| {
+                let relative: Vec<_> = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+                self.pattern.matches_with(&relative.join("/"), options)
+            })
+        } else {
+            self.pattern.matches_with(name, options)
+        }
+    }
+}
+
+/// Rules for the `scan_exclude` globs, which apply at any depth.
+pub fn exclude_rules(globs: &[String]) -> Vec<Arc<IgnoreRule>> {
+    globs.iter().filter_map(|glob| IgnoreRule::parse(Path::new("/"), glob)).map(Arc::new).collect()
+}
+
+/// Whether the last rule matching `path` excludes it, as in git.
+pub fn is_ignored(rules: &[Arc<IgnoreRule>], path: &Path, name: &str, is_dir: bool) -> bool {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| rule.matches(path, name, is_dir))
+        .is_some_and(|rule| !rule.negated)
+}
+
+struct Job {
+    dir: PathBuf,
+    depth: usize,
+    rules: Arc<Vec<Arc<IgnoreRule>>>,
+}
+
+/// State shared by the scanning threads.
+struct Shared {
+    queue: Mutex<VecDeque<Job>>,
+    /// Signalled when jobs are queued or the last busy thread goes idle.
+    ready: Condvar,
+    /// Jobs queued or being worked on; the scan is done when it drops to 0.
+    pending: AtomicUsize,
+    /// Folders already entered, so symlink cycles and overlapping roots
+    /// (home inside `/`) are only walked once.
+    visited: Mutex<HashSet<FolderId>>,
+    /// The index from the last scan; folders and scripts that haven't
+    /// changed since are taken from it instead of being read again.
+    previous: ScriptIndex,
+    folders: Mutex<HashMap<PathBuf, IndexedFolder>>,
+    scripts: Mutex<Vec<IndexedScript>>,
+    files_scanned: AtomicUsize,
+    max_depth: Option<usize>,
+}
+
+impl Shared {
+    fn push(&self, job: Job) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        self.queue.lock().unwrap().push_back(job);
+        self.ready.notify_one();
+    }
+
+    /// Waits for the next job; `None` once no thread has work left.
+    fn next(&self) -> Option<Job> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if let Some(job) = queue.pop_front() {
+                return Some(job);
+            }
+            if self.pending.load(Ordering::SeqCst) == 0 {
+                return None;
+            }
+            queue = self.ready.wait(queue).unwrap();
+        }
+    }
+
+    fn finish_job(&self) {
+        if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Take the lock so no thread misses the wakeup between its checks.
+            let _queue = self.queue.lock().unwrap();
+            self.ready.notify_all();
+        }
+    }
+
+    fn first_visit(&self, dir: &Path) -> bool {
+        match folder_id(dir) {
+            Some(id) => self.visited.lock().unwrap().insert(id),
+            None => false,
+        }
+    }
+
+    fn scan_dir(&self, job: Job) {
+        let modified = folder_modified(&job.dir);
+        let folder = match self.previous.folders.get(&job.dir) {
+            Some(folder) if modified.is_some() && folder.modified == modified => folder.clone(),
+            _ => match self.read_folder(&job.dir, modified) {
+                Some(folder) => folder,
+                None => return,
+            },
+        };
+        let mut rules = job.rules;
+        if let Ok(gitignore) = fs::read_to_string(job.dir.join(".gitignore")) {
+            let mut extended = (*rules).clone();
+            extended.extend(gitignore.lines().filter_map(|line| IgnoreRule::parse(&job.dir, line)).map(Arc::new));
+            rules = Arc::new(extended);
+        }
+        for name in &folder.subfolders {
+            let path = job.dir.join(name);
+            let depth = job.depth + 1;
+            let too_deep = self.max_depth.is_some_and(|max| depth > max);
+            if !too_deep && !is_ignored(&rules, &path, name, true) && !is_pseudo_filesystem(&path) && self.first_visit(&path) {
+                self.push(Job { dir: path, depth, rules: rules.clone() });
+            }
+        }
+        for name in &folder.scripts {
+            let path = job.dir.join(name);
+            if is_ignored(&rules, &path, name, false) {
+                continue;
+            }
+            let script = match self.previous.scripts.get(&path) {
+                Some(script) if script.is_current() => script.clone(),
+                _ => IndexedScript::read(&path),
+            };
+            self.scripts.lock().unwrap().push(script);
+        }
+        self.folders.lock().unwrap().insert(job.dir, folder);
+    }
+
+    /// Lists a folder's subfolders and scripts, before ignore rules.
+    fn read_folder(&self, dir: &Path, modified: Option<(u64, u32)>) -> Option<IndexedFolder> {
+        let mut folder = IndexedFolder { modified, subfolders: Vec::new(), scripts: Vec::new() };
+        for entry in fs::read_dir(dir).ok()?.flatten() {
+            self.files_scanned.fetch_add(1, Ordering::Relaxed);
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Follows symlinks; `first_visit` keeps that from looping.
+            if path.is_dir() {
+                folder.subfolders.push(name);
+            } else if detect::detect(&path).is_some() {
+                folder.scripts.push(name);
+            }
+        }
+        Some(folder)
+    }
+}
+
+/// Lists the scripts for `scan`.
+///
+/// If an earlier scan indexed all of `options.roots`, the indexed scripts are
+/// returned at once and the index is refreshed on a background thread. With
+/// `background` false (the CLI, which is about to exit) the refresh runs
+/// first instead, only re-reading changed folders, and its changes are shown.
+/// Without an index of the roots, or with `rebuild`, everything is scanned.
+pub fn scan(options: &ScanOptions, rebuild: bool, background: bool, quiet: bool) -> Vec<IndexedScript> {
+    let mut previous = ScriptIndex::load().unwrap_or_default();
+    let indexed = options.roots.iter().all(|root| previous.folders.contains_key(root));
+    if rebuild || !indexed {
+        // Keep what other roots found, but read everything below these again.
+        previous.replace(&options.roots, options.max_depth, HashMap::new(), Vec::new());
+        let scripts = refresh(&mut previous, options, quiet);
+        if let Err(e) = previous.save() {
+            eprintln!("Failed to save script index: {}", e);
+        }
+        return scripts;
+    }
+
+    let scripts = previous.scripts_under(&options.roots, options.max_depth);
+    if !quiet {
+        println!("Found {} scripts in the index from {} UTC.", scripts.len(), format_timestamp(previous.updated_at));
+    }
+    let options = options.clone();
+    let update = move || {
+        let mut index = previous;
+        let found = refresh(&mut index, &options, true);
+        if let Err(e) = index.save() {
+            eprintln!("Failed to save script index: {}", e);
+        }
+        REFRESHING.store(false, Ordering::SeqCst);
+        found
+    };
+    if REFRESHING.swap(true, Ordering::SeqCst) {
+        if !quiet {
+            println!("The index is still being refreshed in the background.");
+        }
+    } else if background {
+        if !quiet {
+            println!("Refreshing the index in the background; `scan` again to see changes.");
+        }
+        thread::spawn(update);
+    } else {
+        let found = update();
+        if !quiet {
+            print_changes(&scripts, &found);
+        }
+        return found;
+    }
+    scripts
+}
+
+/// Reports the scripts a refresh added to or removed from the index.
+fn print_changes(before: &[IndexedScript], after: &[IndexedScript]) {
+    let before: HashSet<&Path> = before.iter().map(|script| script.info.path.as_path()).collect();
+    let after: HashSet<&Path> = after.iter().map(|script| script.info.path.as_path()).collect();
+    let mut added: Vec<&Path> = after.difference(&before).copied().collect();
+    let mut removed: Vec<&Path> = before.difference(&after).copied().collect();
+    if added.is_empty() && removed.is_empty() {
+        println!("The index is up to date.");
+        return;
+    }
+    added.sort();
+    removed.sort();
+    println!("Refreshed the index:");
+    for path in added {
+        println!("  + {}", path.display());
+    }
+    for path in removed {
+        println!("  - {}", path.display());
+    }
+}
+
+/// Scans `options.roots` on a pool of threads, updates `index` with the
+/// results and returns the scripts found, sorted by path. Progress is shown
+/// on stdout unless `quiet`.
+fn refresh(index: &mut ScriptIndex, options: &ScanOptions, quiet: bool) -> Vec<IndexedScript> {
+    let exclude = exclude_rules(&options.exclude);
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(VecDeque::new()),
+        ready: Condvar::new(),
+        pending: AtomicUsize::new(0),
+        visited: Mutex::new(HashSet::new()),
+        previous: std::mem::take(index),
+        folders: Mutex::new(HashMap::new()),
+        scripts: Mutex::new(Vec::new()),
+        files_scanned: AtomicUsize::new(0),
+        max_depth: options.max_depth,
+    });
+    let rules = Arc::new(exclude);
+    for root in &options.roots {
+        if root.is_dir() && shared.first_visit(root) {
+            shared.push(Job { dir: root.clone(), depth: 0, rules: rules.clone() });
+        }
+    }
+
+    if !quiet {
+        println!("Scanning filesystem for scripts...");
+    }
+    let start_time = Instant::now();
+    let threads = thread::available_parallelism().map_or(4, |n| n.get()).max(2);
+    let workers: Vec<_> = (0..threads)
+        .map(|_| {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                while let Some(job) = shared.next() {
+                    shared.scan_dir(job);
+                    shared.finish_job();
+                }
+            })
+        })
+        .collect();
+
+    let mut loading_chars = ['⣾', '⣽', '⣻', '⢿', '⡿', '⣟', '⣯', '⣷'].iter().cycle();
+    let mut last_update = Instant::now();
+    while !workers.iter().all(|worker| worker.is_finished()) {
+        thread::sleep(Duration::from_millis(10));
+        if !quiet && last_update.elapsed() >= Duration::from_millis(100) {
+            last_update = Instant::now();
+            print!(
+                "\r{} Files scanned: {}, Scripts found: {}, Time: {:?}    ",
+                loading_chars.next().unwrap(),
+                shared.files_scanned.load(Ordering::Relaxed),
+                shared.scripts.lock().unwrap().len(),
+                start_time.elapsed()
+            );
+            let _ = std::io::stdout().flush();
+        }
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    let Ok(shared) = Arc::try_unwrap(shared) else {
+        unreachable!("all scan threads have been joined");
+    };
+    let mut scripts = shared.scripts.into_inner().unwrap();
+    scripts.sort_by(|a, b| a.info.path.cmp(&b.info.path));
+    if !quiet {
+        println!("\nScan complete! Found {} scripts in {:?}", scripts.len(), start_time.elapsed());
+    }
+    *index = shared.previous;
+    index.replace(&options.roots, options.max_depth, shared.folders.into_inner().unwrap(), scripts.clone());
+    scripts
+}
+
+/// File formats `scan --output` can write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// `[n] path` lines, as listed on screen.
+    Text,
+    /// One JSON array of script objects.
+    Json,
+    /// A header row, then one row per script; lists are joined with `;`.
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+impl ExportFormat {
+    /// The format implied by a file's extension, text if none matches.
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension().and_then(|s| s.to_str()) {
+            Some("json") => Self::Json,
+            Some("csv") => Self::Csv,
+            Some("ndjson" | "jsonl") => Self::Ndjson,
+            _ => Self::Text,
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "ndjson" => Ok(Self::Ndjson),
+            _ => Err(format!("Unknown export format '{}' (expected text, json, csv or ndjson)", s)),
+        }
+    }
+}
+
+/// Writes `scripts` to `out` in `format`. The structured formats carry every
+/// indexed field: path, interpreter, size, modification time (Unix seconds),
+/// exec bit, content hash and the header tags.
+pub fn export(scripts: &[IndexedScript], format: ExportFormat, out: &mut dyn Write) -> io::Result<()> {
+    match format {
+        ExportFormat::Text => {
+            for (i, script) in scripts.iter().enumerate() {
+                writeln!(out, "[{}] {}", i + 1, script.info.path.display())?;
+            }
+        }
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, scripts)?;
+            writeln!(out)?;
+        }
+        ExportFormat::Ndjson => {
+            for script in scripts {
+                serde_json::to_writer(&mut *out, script)?;
+                writeln!(out)?;
+            }
+        }
+        ExportFormat::Csv => {
+            writeln!(out, "path,interpreter,executor,shebang,size,modified,executable,content_hash,description,args,requires,tags")?;
+            for script in scripts {
+                let info = &script.info;
+                let fields = [
+                    info.path.to_string_lossy().into_owned(),
+                    info.interpreter.map(|i| i.to_string()).unwrap_or_default(),
+                    info.executor.map(|i| i.to_string()).unwrap_or_default(),
+                    info.shebang.clone().unwrap_or_default(),
+                    info.size.to_string(),
+                    info.modified.map(|m| m.to_string()).unwrap_or_default(),
+                    info.executable.to_string(),
+                    script.content_hash.clone(),
+                    info.description.clone().unwrap_or_default(),
+                    info.args.clone().unwrap_or_default(),
+                    info.requires.join(";"),
+                    info.tags.join(";"),
+                ];
+                let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+                writeln!(out, "{}", row.join(","))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Saves the list to `path` in `format`.
+pub fn save_list(scripts: &[IndexedScript], path: &Path, format: ExportFormat) {
+    let result = fs::File::create(path).and_then(|file| {
+        let mut out = io::BufWriter::new(file);
+        export(scripts, format, &mut out)?;
+        out.flush()
+    });
+    match result {
+        Ok(()) => println!("Script list saved to {}", path.display()),
+        Err(e) => eprintln!("Failed to save script list to {}: {}", path.display(), e),
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn folder_modified(dir: &Path) -> Option<(u64, u32)> {
+    let modified = fs::metadata(dir).ok()?.modified().ok()?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).ok()?;
+    Some((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
+
+fn is_pseudo_filesystem(path: &Path) -> bool {
+    cfg!(unix) && PSEUDO_FILESYSTEMS.iter().any(|pseudo| path == Path::new(pseudo))
+}
+
+#[cfg(unix)]
+type FolderId = (u64, u64);
+
+/// Device and inode, which stay the same however a folder is reached.
+#[cfg(unix)]
+fn folder_id(dir: &Path) -> Option<FolderId> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::metadata(dir).ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+type FolderId = PathBuf;
+
+/// Without inodes, the canonical path is what stays the same.
+#[cfg(not(unix))]
+fn folder_id(dir: &Path) -> Option<FolderId> {
+    fs::canonicalize(dir).ok()
+}
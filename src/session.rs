@@ -0,0 +1,341 @@
+use crate::logs::Tee;
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::process::ChildStderr;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+#[cfg(not(unix))]
+use std::thread::JoinHandle;
+#[cfg(not(unix))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Result of one command dispatched to a `ShellSession`.
+pub struct CommandStatus {
+    pub code: i32,
+    pub working_dir: PathBuf,
+}
+
+/// Descriptor the shell reports each command's status on. The commands
+/// themselves run with it closed, so neither they nor background jobs they
+/// leave behind hold it open.
+#[cfg(unix)]
+const STATUS_FD: i32 = 4;
+
+/// A single long-lived shell process that runs script commands one at a time.
+///
+/// Commands are written to bash's stdin and evaluated in the current shell, so
+/// variables, functions, `cd`, `set` options and sourced files carry over from
+/// one command to the next. After every command bash reports the exit status
+/// and `$PWD` on a pipe of its own, so scripts that redirect or close stdout,
+/// or leave a background job holding it, don't keep magish waiting. WSL can't
+/// pass extra descriptors, so on Windows the status is a marker line on
+/// stdout instead, stripped from the forwarded output.
+pub struct ShellSession {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    /// `None` once the shell closed it.
+    stdout: Option<ChildStdout>,
+    output: Tee,
+    #[cfg(unix)]
+    stderr: Option<ChildStderr>,
+    #[cfg(unix)]
+    errors: Tee,
+    #[cfg(unix)]
+    status: Option<io::PipeReader>,
+    #[cfg(not(unix))]
+    stderr_copy: Option<JoinHandle<()>>,
+    #[cfg(not(unix))]
+    marker: String,
+    /// Status bytes read but not yet parsed (on Windows, stdout bytes).
+    pending: Vec<u8>,
+}
+
+impl ShellSession {
+    /// Starts `command`, a shell reading commands from stdin (see
+    /// `Executor::session_command`), in `working_dir` with `env` added to its
+    /// environment. Its output is passed on through the `output` and `errors`
+    /// tees.
+    pub fn start(mut command: Command, working_dir: &Path, env: &[(String, String)], output: Tee, errors: Tee) -> io::Result<Self> {
+        set_env(&mut command, env);
+        command.current_dir(working_dir).stdin(Stdio::piped()).stderr(errors.stdio());
+        // Without a status pipe the status comes on stdout, so it always has to be read.
+        command.stdout(if cfg!(unix) { output.stdio() } else { Stdio::piped() });
+        #[cfg(unix)]
+        let (status, status_writer) = io::pipe()?;
+        #[cfg(unix)]
+        pass_fd(&mut command, &status_writer, STATUS_FD);
+        let mut child = command.spawn()?;
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take();
+
+        #[cfg(unix)]
+        let mut session = {
+            // Only the shell may hold the write end, or the pipe never closes.
+            drop(status_writer);
+            Self {
+                stderr: child.stderr.take(),
+                child,
+                stdin,
+                stdout,
+                output,
+                errors,
+                status: Some(status),
+                pending: Vec::new(),
+            }
+        };
+        #[cfg(not(unix))]
+        let mut session = {
+            let stderr_copy = child.stderr.take().map(|stderr| errors.spawn_copy(stderr));
+            let nonce = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+            Self {
+                child,
+                stdin,
+                stdout,
+                output,
+                stderr_copy,
+                marker: format!("__MAGISH_{}_{}__", std::process::id(), nonce),
+                pending: Vec::new(),
+            }
+        };
+        // bash reads commands from our pipe, so give the commands themselves the
+        // terminal (or nothing) on fd 3 to read from instead.
+        session.send(concat!(
+            "if (: </dev/tty) 2>/dev/null; then exec 3</dev/tty; else exec 3</dev/null; fi\n",
+            "__magish_status() { return \"$1\"; }\n",
+            "__magish_rc=0\n",
+        ))?;
+        Ok(session)
+    }
+
+    /// Runs one command and waits for it to finish.
+    /// Returns `None` if the shell exited while running it (e.g. `exit` or `set -e`).
+    pub fn run(&mut self, cmd: &str) -> io::Result<Option<CommandStatus>> {
+        let cmd = cmd.replace('\'', "'\\''");
+        #[cfg(unix)]
+        let script = format!(
+            "{{ case $- in *e*) ;; *) __magish_status \"$__magish_rc\" ;; esac; eval '{}'\n}} 0<&3 {}>&-\n\
+             __magish_rc=$?\n\
+             printf '%d:%s\\n' \"$__magish_rc\" \"$PWD\" >&{}\n",
+            cmd, STATUS_FD, STATUS_FD,
+        );
+        #[cfg(not(unix))]
+        let script = format!(
+            "{{ case $- in *e*) ;; *) __magish_status \"$__magish_rc\" ;; esac; eval '{}'\n}} 0<&3\n\
+             __magish_rc=$?\n\
+             printf '%s:%d:%s\\n' '{}' \"$__magish_rc\" \"$PWD\"\n",
+            cmd, self.marker,
+        );
+        // If the shell is already gone, whatever it printed is still worth showing.
+        let _ = self.send(&script);
+        self.read_status()
+    }
+
+    /// Closes the session and returns the shell's exit code.
+    pub fn finish(mut self) -> io::Result<i32> {
+        self.stdin.take();
+        while self.read_status()?.is_some() {}
+        let status = self.child.wait()?;
+        #[cfg(unix)]
+        self.forward_ready()?;
+        #[cfg(not(unix))]
+        if let Some(copy) = self.stderr_copy.take() {
+            let _ = copy.join();
+        }
+        Ok(status.code().unwrap_or(-1))
+    }
+
+    fn send(&mut self, text: &str) -> io::Result<()> {
+        let stdin = self.stdin.as_mut().ok_or_else(|| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        stdin.write_all(text.as_bytes())?;
+        stdin.flush()
+    }
+
+    /// Forwards output until the next status line, or until the shell exits.
+    #[cfg(unix)]
+    fn read_status(&mut self) -> io::Result<Option<CommandStatus>> {
+        let mut buf = [0u8; 4096];
+        loop {
+            if let Some(nl) = self.pending.iter().position(|&b| b == b'\n') {
+                let line = String::from_utf8_lossy(&self.pending[..nl]).into_owned();
+                self.pending.drain(..=nl);
+                // The command's output was written before its status, so it is
+                // all in the pipes by now.
+                self.forward_ready()?;
+                return Ok(Some(parse_status(&line)));
+            }
+            let Some(status) = self.status.as_ref().map(AsRawFd::as_raw_fd) else {
+                self.forward_ready()?;
+                return Ok(None);
+            };
+            let ready = poll(&[raw_fd(&self.stdout), raw_fd(&self.stderr), status], -1)?;
+            self.forward(ready[0], ready[1])?;
+            if ready[2] {
+                if let Some(reader) = self.status.as_mut() {
+                    match reader.read(&mut buf)? {
+                        // The shell exited; background jobs may still hold stdout,
+                        // so don't wait for it to close.
+                        0 => self.status = None,
+                        n => self.pending.extend_from_slice(&buf[..n]),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forwards whatever stdout and stderr have ready, without waiting.
+    #[cfg(unix)]
+    fn forward_ready(&mut self) -> io::Result<()> {
+        loop {
+            let ready = poll(&[raw_fd(&self.stdout), raw_fd(&self.stderr)], 0)?;
+            if !ready.contains(&true) {
+                return Ok(());
+            }
+            self.forward(ready[0], ready[1])?;
+        }
+    }
+
+    #[cfg(unix)]
+    fn forward(&mut self, stdout: bool, stderr: bool) -> io::Result<()> {
+        let mut buf = [0u8; 4096];
+        if let Some(reader) = self.stdout.as_mut().filter(|_| stdout) {
+            match reader.read(&mut buf)? {
+                0 => self.stdout = None,
+                n => self.output.write(&buf[..n])?,
+            }
+        }
+        if let Some(reader) = self.stderr.as_mut().filter(|_| stderr) {
+            match reader.read(&mut buf)? {
+                0 => self.stderr = None,
+                n => self.errors.write(&buf[..n])?,
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn read_status(&mut self) -> io::Result<Option<CommandStatus>> {
+        let mut buf = [0u8; 4096];
+        loop {
+            let marker = self.marker.as_bytes();
+            if let Some(pos) = find(&self.pending, marker) {
+                if let Some(nl) = self.pending[pos..].iter().position(|&b| b == b'\n') {
+                    self.output.write(&self.pending[..pos])?;
+                    let line = String::from_utf8_lossy(&self.pending[pos + marker.len()..pos + nl]).into_owned();
+                    self.pending.drain(..pos + nl + 1);
+                    return Ok(Some(parse_status(line.strip_prefix(':').unwrap_or(&line))));
+                }
+            } else {
+                // Hold back only a tail that could be the start of the marker, so
+                // prompts without a trailing newline still show up immediately.
+                let keep = partial_marker_len(&self.pending, marker);
+                let flush_to = self.pending.len() - keep;
+                self.output.write(&self.pending[..flush_to])?;
+                self.pending.drain(..flush_to);
+            }
+
+            let n = match self.stdout.as_mut() {
+                Some(stdout) => stdout.read(&mut buf)?,
+                None => 0,
+            };
+            if n == 0 {
+                self.output.write(&self.pending)?;
+                self.pending.clear();
+                self.stdout = None;
+                return Ok(None);
+            }
+            self.pending.extend_from_slice(&buf[..n]);
+        }
+    }
+}
+
+impl Drop for ShellSession {
+    fn drop(&mut self) {
+        self.stdin.take();
+        let _ = self.child.wait();
+    }
+}
+
+/// Adds `env` to a bash command. WSL only passes through variables listed in
+/// `WSLENV`, so on Windows they are appended there as well.
+pub fn set_env(command: &mut Command, env: &[(String, String)]) {
+    command.envs(env.iter().map(|(key, value)| (key, value)));
+    if cfg!(target_os = "windows") && !env.is_empty() {
+        let mut wslenv = std::env::var("WSLENV").unwrap_or_default();
+        for (key, _) in env {
+            if !wslenv.is_empty() {
+                wslenv.push(':');
+            }
+            wslenv.push_str(key);
+        }
+        command.env("WSLENV", wslenv);
+    }
+}
+
+/// Makes `fd` in the child a copy of `file`, open across `exec`.
+#[cfg(unix)]
+fn pass_fd(command: &mut Command, file: &impl AsRawFd, fd: i32) {
+    use std::os::unix::process::CommandExt;
+    let source = file.as_raw_fd();
+    // SAFETY: only async-signal-safe calls between fork and exec.
+    unsafe {
+        command.pre_exec(move || {
+            let result = if source == fd {
+                // dup2 onto itself would leave close-on-exec set.
+                libc::fcntl(fd, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(source, fd)
+            };
+            if result == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(unix)]
+fn raw_fd(stream: &Option<impl AsRawFd>) -> RawFd {
+    // poll skips negative descriptors.
+    stream.as_ref().map_or(-1, AsRawFd::as_raw_fd)
+}
+
+/// Waits up to `timeout_ms` (forever if negative) for any of `fds` to be
+/// readable or closed, and returns which are.
+#[cfg(unix)]
+fn poll(fds: &[RawFd], timeout_ms: i32) -> io::Result<Vec<bool>> {
+    let mut pollfds: Vec<libc::pollfd> = fds.iter().map(|&fd| libc::pollfd { fd, events: libc::POLLIN, revents: 0 }).collect();
+    loop {
+        // SAFETY: `pollfds` is a valid array of `pollfds.len()` entries.
+        if unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout_ms) } >= 0 {
+            return Ok(pollfds.iter().map(|pollfd| pollfd.revents != 0).collect());
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(not(unix))]
+fn partial_marker_len(buf: &[u8], marker: &[u8]) -> usize {
+    (1..marker.len().min(buf.len() + 1))
+        .rev()
+        .find(|&k| buf.ends_with(&marker[..k]))
+        .unwrap_or(0)
+}
+
+/// Parses a `<code>:<pwd>` status line.
+fn parse_status(line: &str) -> CommandStatus {
+    let (code, dir) = line.split_once(':').unwrap_or((line, ""));
+    CommandStatus {
+        code: code.trim().parse().unwrap_or(-1),
+        working_dir: PathBuf::from(dir),
+    }
+}
@@ -0,0 +1,119 @@
+use crate::parser::Statement;
+use rustyline::DefaultEditor;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::process::Command;
+
+/// What to do with the statement the stepper paused at.
+pub enum StepAction {
+    /// Run this text, which is the statement itself unless it was edited.
+    Run(String),
+    Skip,
+    Abort,
+}
+
+/// Interactive pause-before-each-command control for `step` runs.
+pub struct Stepper {
+    editor: Option<DefaultEditor>,
+    breakpoints: BTreeSet<usize>,
+    /// Run without pausing until the next breakpoint.
+    continuing: bool,
+}
+
+impl Stepper {
+    pub fn new() -> Self {
+        println!("Stepping through script. At each command: [Enter/r]un, [s]kip, [e]dit, s[h]ell,");
+        println!("[b] <line> toggle breakpoint, [l]ist breakpoints, [c]ontinue, [q]uit.");
+        Self {
+            editor: DefaultEditor::new().ok(),
+            breakpoints: BTreeSet::new(),
+            continuing: false,
+        }
+    }
+
+    /// Decides what to do with `statement`, pausing to ask unless continuing
+    /// past it.
+    pub fn pause(&mut self, statement: &Statement, working_dir: &Path) -> StepAction {
+        let hit = self.breakpoints.range(statement.line..=statement.end_line()).next().is_some();
+        if self.continuing && !hit {
+            return StepAction::Run(statement.text.clone());
+        }
+        self.continuing = false;
+
+        println!("\n-- line {}{} in {} --", statement.line, if hit { " (breakpoint)" } else { "" }, working_dir.display());
+        println!("{}", statement.text);
+        loop {
+            let Some(input) = self.read("step> ", "") else {
+                return StepAction::Abort;
+            };
+            let mut words = input.split_whitespace();
+            match (words.next().unwrap_or("r"), words.next()) {
+                ("r" | "run", _) => return StepAction::Run(statement.text.clone()),
+                ("s" | "skip", _) => return StepAction::Skip,
+                ("e" | "edit", _) => {
+                    if let Some(edited) = self.read("edit> ", &statement.text) {
+                        return StepAction::Run(edited);
+                    }
+                }
+                ("h" | "sh" | "shell", _) => open_shell(working_dir),
+                ("b" | "break", Some(line)) => match line.parse::<usize>() {
+                    Ok(line) => {
+                        if self.breakpoints.remove(&line) {
+                            println!("Removed breakpoint at line {}", line);
+                        } else {
+                            self.breakpoints.insert(line);
+                            println!("Breakpoint set at line {}", line);
+                        }
+                    }
+                    Err(_) => println!("Invalid line number: {}", line),
+                },
+                ("b" | "break", None) => println!("Usage: b <line>"),
+                ("l" | "list", _) => {
+                    if self.breakpoints.is_empty() {
+                        println!("No breakpoints set.");
+                    }
+                    for line in &self.breakpoints {
+                        println!("  line {}", line);
+                    }
+                }
+                ("c" | "continue", _) => {
+                    self.continuing = true;
+                    return StepAction::Run(statement.text.clone());
+                }
+                ("q" | "quit", _) => return StepAction::Abort,
+                (other, _) => println!("Unknown step command: {}", other),
+            }
+        }
+    }
+
+    fn read(&mut self, prompt: &str, initial: &str) -> Option<String> {
+        match self.editor.as_mut() {
+            Some(editor) => editor.readline_with_initial(prompt, (initial, "")).ok(),
+            None => {
+                // No line editor (e.g. input is not a terminal); fall back to plain stdin.
+                print!("{}", prompt);
+                let _ = std::io::Write::flush(&mut std::io::stdout());
+                let mut line = String::new();
+                match std::io::stdin().read_line(&mut line) {
+                    Ok(n) if n > 0 => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// Opens an interactive shell in the script's current folder until it exits.
+fn open_shell(working_dir: &Path) {
+    println!("Opening a shell in {}. Exit it to return to the stepper.", working_dir.display());
+    let mut command = if cfg!(target_os = "windows") {
+        let mut c = Command::new("wsl");
+        c.arg("bash");
+        c
+    } else {
+        Command::new("bash")
+    };
+    if let Err(e) = command.current_dir(working_dir).status() {
+        eprintln!("Failed to open shell: {}", e);
+    }
+}
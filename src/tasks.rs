@@ -0,0 +1,281 @@
+//! Named tasks from a project's `magish.toml`.
+//!
+//! ```toml
+//! default = "deploy"
+//!
+//! [tasks.build]
+//! description = "Build the site"
+//! run = "npm ci && npm run build"
+//!
+//! [tasks.deploy]
+//! script = "scripts/deploy.sh"
+//! args = ["staging"]
+//! env = { DRY_RUN = "0" }
+//! depends_on = ["build"]
+//! ```
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+const TASK_FILE: &str = "magish.toml";
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Task {
+    pub description: Option<String>,
+    /// Script to run, relative to the folder of `magish.toml`.
+    pub script: Option<PathBuf>,
+    /// Shell commands to run instead of a script.
+    pub run: Option<String>,
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    /// Working folder, relative to the folder of `magish.toml`; that folder
+    /// itself if not given.
+    pub cwd: Option<PathBuf>,
+    /// Tasks that have to succeed before this one runs.
+    pub depends_on: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct TaskFileContents {
+    default: Option<String>,
+    tasks: BTreeMap<String, Task>,
+}
+
+/// A `magish.toml` and the tasks it defines.
+#[derive(Debug)]
+pub struct TaskFile {
+    pub path: PathBuf,
+    /// Task run when Enter is pressed on an empty prompt.
+    pub default: Option<String>,
+    /// Tasks by name.
+    pub tasks: BTreeMap<String, Task>,
+}
+
+impl TaskFile {
+    /// The `magish.toml` in `dir` or the nearest parent that has one, or
+    /// `None` if there is none. Fails if the file can't be read or is invalid.
+    pub fn find(dir: &Path) -> Result<Option<Self>, String> {
+        match dir.ancestors().map(|folder| folder.join(TASK_FILE)).find(|path| path.is_file()) {
+            Some(path) => Self::read(&path).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the task file at `path`.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let error = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+        let text = fs::read_to_string(path).map_err(|e| error(&e))?;
+        let contents: TaskFileContents = toml::from_str(&text).map_err(|e| error(&e))?;
+        let file = Self { path: path.to_path_buf(), default: contents.default, tasks: contents.tasks };
+        file.check().map_err(|e| error(&e))?;
+        Ok(file)
+    }
+
+    /// Folder the task paths are relative to.
+    pub fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new("."))
+    }
+
+    /// Names of the tasks in numbered-picker order.
+    pub fn names(&self) -> Vec<&str> {
+        self.tasks.keys().map(String::as_str).collect()
+    }
+
+    pub fn working_dir(&self, task: &Task) -> PathBuf {
+        task.cwd.as_ref().map_or_else(|| self.dir().to_path_buf(), |cwd| self.dir().join(cwd))
+    }
+
+    /// Where the run log says a task's commands came from: its script, or
+    /// `magish.toml:<name>` for inline commands.
+    pub fn source(&self, name: &str, task: &Task) -> PathBuf {
+        match &task.script {
+            Some(script) => self.dir().join(script),
+            None => PathBuf::from(format!("{}:{}", self.path.display(), name)),
+        }
+    }
+
+    /// `name` and everything it depends on, each after its dependencies.
+    pub fn plan(&self, name: &str) -> Result<Vec<&str>, String> {
+        let mut order = Vec::new();
+        self.visit(name, &mut Vec::new(), &mut order)?;
+        Ok(order)
+    }
+
+    fn visit<'a>(&'a self, name: &str, path: &mut Vec<&'a str>, order: &mut Vec<&'a str>) -> Result<(), String> {
+        let Some((name, task)) = self.tasks.get_key_value(name) else {
+            return Err(format!("Unknown task '{}'", name));
+        };
+        if order.contains(&name.as_str()) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|visiting| *visiting == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name);
+            return Err(format!("Tasks depend on each other in a cycle: {}", cycle.join(" -> ")));
+        }
+        path.push(name);
+        for dependency in &task.depends_on {
+            self.visit(dependency, path, order)?;
+        }
+        path.pop();
+        order.push(name);
+        Ok(())
+    }
+
+    fn check(&self) -> Result<(), String> {
+        for (name, task) in &self.tasks {
+            if task.script.is_some() == task.run.is_some() {
+                return Err(format!("task '{}' needs either `script` or `run`", name));
+            }
+            if let Some(unknown) = task.depends_on.iter().find(|dependency| !self.tasks.contains_key(*dependency)) {
+                return Err(format!("task '{}' depends on unknown task '{}'", name, unknown));
+            }
+            self.plan(name)?;
+        }
+        match &self.default {
+            Some(default) if !self.tasks.contains_key(default) => Err(format!("unknown default task '{}'", default)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs the tasks of `plan` with `run`, each once all its dependencies
+    /// have succeeded, and returns the first non-zero status. With
+    /// `parallel`, tasks that don't depend on each other run at the same time.
+    pub fn run_plan(&self, plan: &[&str], parallel: bool, run: impl Fn(&str, &Task) -> i32 + Sync) -> i32 {
+        let mut done: HashSet<&str> = HashSet::new();
+        let mut started: HashSet<&str> = HashSet::new();
+        let mut failed = 0;
+        let (finished, results) = mpsc::channel();
+        thread::scope(|scope| {
+            let mut running = 0;
+            loop {
+                if failed == 0 {
+                    // Start whatever is ready, in plan order.
+                    for &name in plan {
+                        let task = &self.tasks[name];
+                        let ready = !started.contains(name) && task.depends_on.iter().all(|dependency| done.contains(dependency.as_str()));
+                        if ready && (parallel || running == 0) {
+                            started.insert(name);
+                            running += 1;
+                            let (finished, run) = (finished.clone(), &run);
+                            scope.spawn(move || finished.send((name, run(name, task))));
+                        }
+                    }
+                }
+                if running == 0 {
+                    break;
+                }
+                let Ok((name, status)) = results.recv() else {
+                    break;
+                };
+                running -= 1;
+                if status == 0 {
+                    done.insert(name);
+                } else if failed == 0 {
+                    failed = status;
+                }
+            }
+        });
+        failed
+    }
+
+    /// Prints the tasks numbered from `first`.
+    pub fn print(&self, first: usize) {
+        let width = self.tasks.keys().map(String::len).max().unwrap_or(0);
+        for (i, (name, task)) in self.tasks.iter().enumerate() {
+            let mut line = format!("  [{}] {:<width$}  {}", first + i, name, task.description.as_deref().unwrap_or(""), width = width);
+            if !task.depends_on.is_empty() {
+                line.push_str(&format!(" (after {})", task.depends_on.join(", ")));
+            }
+            if self.default.as_deref() == Some(name) {
+                line.push_str(" [default]");
+            }
+            println!("{}", line.trim_end());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// A task file whose tasks run `true` and depend on the given tasks.
+    fn file(tasks: &[(&str, &[&str])]) -> TaskFile {
+        let tasks = tasks
+            .iter()
+            .map(|(name, depends_on)| {
+                let task = Task {
+                    run: Some("true".to_string()),
+                    depends_on: depends_on.iter().map(|name| name.to_string()).collect(),
+                    ..Task::default()
+                };
+                (name.to_string(), task)
+            })
+            .collect();
+        TaskFile { path: PathBuf::from("magish.toml"), default: None, tasks }
+    }
+
+    #[test]
+    fn plan_puts_dependencies_first() {
+        let file = file(&[("deploy", &["test", "build"]), ("test", &["build"]), ("build", &[]), ("lint", &[])]);
+        assert_eq!(file.plan("deploy").unwrap(), vec!["build", "test", "deploy"]);
+        assert_eq!(file.plan("lint").unwrap(), vec!["lint"]);
+        assert_eq!(file.plan("nope").unwrap_err(), "Unknown task 'nope'");
+    }
+
+    #[test]
+    fn plan_runs_shared_dependencies_once() {
+        let file = file(&[("all", &["left", "right"]), ("left", &["base"]), ("right", &["base"]), ("base", &[])]);
+        assert_eq!(file.plan("all").unwrap(), vec!["base", "left", "right", "all"]);
+    }
+
+    #[test]
+    fn plan_reports_cycles() {
+        let file = file(&[("a", &["b"]), ("b", &["c"]), ("c", &["b"])]);
+        assert_eq!(file.plan("a").unwrap_err(), "Tasks depend on each other in a cycle: b -> c -> b");
+        assert!(file.check().is_err());
+    }
+
+    #[test]
+    fn run_plan_stops_after_a_failure() {
+        let file = file(&[("all", &["first", "second"]), ("first", &[]), ("second", &["first"]), ("other", &[])]);
+        let plan = ["first", "other", "second", "all"];
+        for parallel in [false, true] {
+            let ran = Mutex::new(Vec::new());
+            let status = file.run_plan(&plan, parallel, |name, _| {
+                ran.lock().unwrap().push(name.to_string());
+                if name == "first" {
+                    3
+                } else {
+                    0
+                }
+            });
+            assert_eq!(status, 3);
+            let ran = ran.into_inner().unwrap();
+            assert!(!ran.contains(&"second".to_string()) && !ran.contains(&"all".to_string()), "{:?}", ran);
+        }
+    }
+
+    #[test]
+    fn run_plan_runs_everything_in_order() {
+        let file = file(&[("all", &["left", "right"]), ("left", &["base"]), ("right", &["base"]), ("base", &[])]);
+        let plan = file.plan("all").unwrap();
+        let ran = Mutex::new(Vec::new());
+        let status = file.run_plan(&plan, true, |name, _| {
+            ran.lock().unwrap().push(name.to_string());
+            0
+        });
+        assert_eq!(status, 0);
+        let ran = ran.into_inner().unwrap();
+        assert_eq!(ran.len(), 4);
+        assert_eq!(ran.first().map(String::as_str), Some("base"));
+        assert_eq!(ran.last().map(String::as_str), Some("all"));
+    }
+}
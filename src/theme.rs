@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use std::io::IsTerminal;
+
+/// ANSI colors used by magish, as SGR codes such as `"1;34"`. An empty code
+/// leaves that element uncolored.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Theme {
+    pub prompt: String,
+    pub directory: String,
+    pub script: String,
+    pub hint: String,
+    pub warning: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            prompt: String::new(),
+            directory: "1;34".to_string(),
+            script: "1;32".to_string(),
+            hint: "2".to_string(),
+            warning: "1;33".to_string(),
+        }
+    }
+}
+
+impl Theme {
+    /// Wraps `text` in the color `code`, unless colors are disabled.
+    pub fn paint(&self, code: &str, text: &str) -> String {
+        if code.is_empty() || !colors_enabled() {
+            text.to_string()
+        } else {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        }
+    }
+}
+
+/// Colors are off when `NO_COLOR` is set (see https://no-color.org) or when
+/// stdout is not a terminal.
+pub fn colors_enabled() -> bool {
+    std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && std::io::stdout().is_terminal()
+}